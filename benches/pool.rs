@@ -0,0 +1,128 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// cargo bench --bench pool
+
+use cita_cloud_proto::blockchain::raw_transaction::Tx;
+use cita_cloud_proto::blockchain::{RawTransaction, Transaction, UnverifiedTransaction, Witness};
+use controller::pool::{FifoPolicy, PackageLimit, Pool, PoolLimit};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const PENDING: u64 = 100_000;
+const BLOCK_TXS: u64 = 6_000;
+const BLOCKS: u64 = 10;
+
+fn pending_txs() -> Vec<(Vec<u8>, RawTransaction)> {
+    (0..PENDING)
+        .map(|i| {
+            let tx_hash = i.to_be_bytes().to_vec();
+            let raw_tx = RawTransaction {
+                tx: Some(Tx::NormalTx(UnverifiedTransaction {
+                    transaction: Some(Transaction {
+                        valid_until_block: 50,
+                        ..Default::default()
+                    }),
+                    transaction_hash: tx_hash.clone(),
+                    witness: Some(Witness {
+                        sender: vec![(i % 256) as u8; 20],
+                        ..Default::default()
+                    }),
+                })),
+            };
+            (tx_hash, raw_tx)
+        })
+        .collect()
+}
+
+// tx hashes of finalized blocks, each removed from pool in turn
+fn finalized_blocks() -> Vec<Vec<Vec<u8>>> {
+    (0..BLOCKS)
+        .map(|b| {
+            (b * BLOCK_TXS..(b + 1) * BLOCK_TXS)
+                .map(|i| i.to_be_bytes().to_vec())
+                .collect()
+        })
+        .collect()
+}
+
+fn bench_update(c: &mut Criterion) {
+    let raw_txs = pending_txs();
+    let blocks = finalized_blocks();
+    let mut group = c.benchmark_group("pool update 100k pending txs");
+    group.sample_size(10);
+
+    // the rebuild-on-update behavior the pool replaced
+    group.bench_function("rebuild", |b| {
+        b.iter_batched(
+            || {
+                let mut order_set: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+                let mut txs: HashMap<Vec<u8>, RawTransaction> = HashMap::new();
+                for (order, (hash, raw_tx)) in raw_txs.iter().enumerate() {
+                    order_set.insert(order as u64, hash.clone());
+                    txs.insert(hash.clone(), raw_tx.clone());
+                }
+                (order_set, txs)
+            },
+            |(mut order_set, mut txs)| {
+                for block in blocks.iter() {
+                    let tx_hash_list: HashSet<&Vec<u8>> = block.iter().collect();
+                    txs = txs
+                        .clone()
+                        .into_iter()
+                        .filter(|(hash, _)| !tx_hash_list.contains(hash))
+                        .collect();
+                    order_set = order_set
+                        .clone()
+                        .into_iter()
+                        .filter(|(_, hash)| !tx_hash_list.contains(hash))
+                        .collect();
+                }
+                (order_set, txs)
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("incremental", |b| {
+        b.iter_batched(
+            || {
+                let mut pool = Pool::new(
+                    PackageLimit {
+                        tx_limit: BLOCK_TXS as usize,
+                        ..Default::default()
+                    },
+                    Box::new(FifoPolicy),
+                    PoolLimit::default(),
+                );
+                for (hash, raw_tx) in raw_txs.iter() {
+                    pool.enqueue(hash.clone(), raw_tx.clone()).unwrap();
+                }
+                pool
+            },
+            |mut pool| {
+                for block in blocks.iter() {
+                    pool.update(block);
+                }
+                pool
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_update);
+criterion_main!(benches);
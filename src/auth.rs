@@ -12,21 +12,64 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::util::{get_compact_block, verify_tx_hash, verify_tx_signature};
-use crate::utxo_set::{SystemConfig, LOCK_ID_BUTTON, LOCK_ID_VERSION};
+use crate::error::{Error, RejectReason};
+use crate::util::{
+    db_get_tx, get_compact_block, load_data_maybe_empty, verify_tx_hash, verify_tx_signature,
+};
+use crate::utxo_set::{
+    decode_scheduled, history_records, SystemConfig, LOCK_ID_BLOCK_LIMIT, LOCK_ID_BUTTON,
+    LOCK_ID_HISTORY_FLAG, LOCK_ID_SCHEDULED_FLAG, LOCK_ID_VERSION,
+};
 use cita_cloud_proto::blockchain::raw_transaction::Tx::{NormalTx, UtxoTx};
 use cita_cloud_proto::blockchain::RawTransaction;
-use cita_cloud_proto::blockchain::{Transaction, UnverifiedUtxoTransaction, UtxoTransaction};
+use cita_cloud_proto::blockchain::{Transaction, UtxoTransaction};
 use prost::Message;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::iter::FromIterator;
 
-pub const BLOCKLIMIT: u64 = 100;
+// spawning a task for fewer txs costs more than it saves
+const MIN_VERIFY_CHUNK_SIZE: usize = 64;
+
+// (height, evict height) of blocks still in history after block `height`.
+// block h is checked with the block limit in force after block h - 1 finalized,
+// `changes` are (height applied, block limit) in the order applied.
+fn history_window(height: u64, init_block_limit: u64, changes: &[(u64, u64)]) -> Vec<(u64, u64)> {
+    // (first, last, block limit) of blocks checked with the same limit
+    let mut segments = Vec::new();
+    let mut first = 1;
+    let mut block_limit = init_block_limit;
+    for &(applied, new_block_limit) in changes {
+        if applied >= first {
+            segments.push((first, applied, block_limit));
+            first = applied + 1;
+        }
+        block_limit = new_block_limit;
+    }
+    segments.push((first, height, block_limit));
+
+    segments
+        .into_iter()
+        .flat_map(|(first, last, block_limit)| {
+            // blocks evicted at or before height are gone
+            let first = first.max((height + 1).saturating_sub(block_limit));
+            (first..=last.min(height)).map(move |h| (h, h.saturating_add(block_limit)))
+        })
+        .collect()
+}
 
 #[derive(Clone)]
 pub struct Authentication {
-    history_hashes: HashMap<u64, HashSet<Vec<u8>>>,
+    // height - (evict height, tx hashes of that block)
+    history_hashes: HashMap<u64, (u64, HashSet<Vec<u8>>)>,
+    // (evict height, height) of blocks in history_hashes.
+    // block h is evicted at h + block limit which its txs were checked with,
+    // so a block limit change only affects blocks after it.
+    history_evictions: BTreeSet<(u64, u64)>,
+    // block limit which txs of the next block are checked with
+    block_limit: u64,
+    // tx hash - heights in history_hashes which contain it
+    tx_heights: HashMap<Vec<u8>, BTreeSet<u64>>,
     current_block_number: u64,
     sys_config: SystemConfig,
 }
@@ -35,7 +78,10 @@ impl Authentication {
     pub fn new(sys_config: SystemConfig) -> Self {
         Authentication {
             history_hashes: HashMap::new(),
+            history_evictions: BTreeSet::new(),
+            tx_heights: HashMap::new(),
             current_block_number: 0,
+            block_limit: sys_config.block_limit,
             sys_config,
         }
     }
@@ -44,168 +90,700 @@ impl Authentication {
         self.sys_config.clone()
     }
 
-    pub fn update_system_config(&mut self, tx: &UnverifiedUtxoTransaction) -> bool {
-        self.sys_config.update(tx, false)
+    pub fn set_system_config(&mut self, sys_config: SystemConfig) {
+        self.sys_config = sys_config;
     }
 
-    pub async fn init(&mut self, init_block_number: u64) {
-        let begin_block_number = if init_block_number >= BLOCKLIMIT {
-            init_block_number - BLOCKLIMIT + 1
-        } else {
-            1u64
-        };
-
-        for h in begin_block_number..(init_block_number + 1) {
+    // rebuild history with the block limit each block was checked with, a block limit
+    // lowered since must not forget blocks checked with the higher one
+    pub async fn init(&mut self, init_block_number: u64, init_block_limit: u64) {
+        let changes = self.load_block_limit_changes().await;
+        for (h, evict_height) in history_window(init_block_number, init_block_limit, &changes) {
             let block = get_compact_block(h).await.unwrap().0;
             let block_body = block.body.unwrap();
-            self.history_hashes
-                .insert(h, HashSet::from_iter(block_body.tx_hashes));
+            self.insert_history(h, evict_height, HashSet::from_iter(block_body.tx_hashes));
         }
         self.current_block_number = init_block_number;
     }
 
+    // (height applied, block limit) of block limit changes in order, from region 0 history
+    async fn load_block_limit_changes(&self) -> Vec<(u64, u64)> {
+        let key = (LOCK_ID_BLOCK_LIMIT | LOCK_ID_HISTORY_FLAG)
+            .to_be_bytes()
+            .to_vec();
+        let history = load_data_maybe_empty(0, key).await.unwrap();
+        let mut changes = Vec::new();
+        for (height, tx_hash) in history_records(&history) {
+            if let Some(UtxoTx(utxo_tx)) = db_get_tx(&tx_hash).await.unwrap().tx {
+                // applied as restart does
+                let mut sys_config = self.sys_config.clone();
+                if sys_config.update(&utxo_tx, true) {
+                    changes.push((height, sys_config.block_limit));
+                }
+            }
+        }
+        changes
+    }
+
     pub fn insert_tx_hash(&mut self, h: u64, hash_list: Vec<Vec<u8>>) {
-        self.history_hashes.insert(h, HashSet::from_iter(hash_list));
-        if h >= BLOCKLIMIT {
-            self.history_hashes.remove(&(h - BLOCKLIMIT));
+        // utxo txs of block h are applied before, sys_config.block_limit may be changed by h
+        self.insert_history(h, h + self.block_limit, HashSet::from_iter(hash_list));
+        self.block_limit = self.sys_config.block_limit;
+        while let Some(&(evict_height, height)) = self.history_evictions.iter().next() {
+            if evict_height > h {
+                break;
+            }
+            self.remove_history(height);
         }
         if h > self.current_block_number {
             self.current_block_number = h;
         }
     }
 
-    #[allow(clippy::ptr_arg)]
-    fn check_tx_hash(&self, tx_hash: &Vec<u8>) -> Result<(), String> {
-        for (_h, hash_list) in self.history_hashes.iter() {
-            if hash_list.contains(tx_hash) {
-                return Err("dup".to_owned());
+    fn insert_history(&mut self, h: u64, evict_height: u64, hashes: HashSet<Vec<u8>>) {
+        // same height inserted again replaces the old hashes
+        self.remove_history(h);
+        for hash in hashes.iter() {
+            self.tx_heights.entry(hash.clone()).or_default().insert(h);
+        }
+        self.history_hashes.insert(h, (evict_height, hashes));
+        self.history_evictions.insert((evict_height, h));
+    }
+
+    fn remove_history(&mut self, h: u64) {
+        if let Some((evict_height, hashes)) = self.history_hashes.remove(&h) {
+            self.history_evictions.remove(&(evict_height, h));
+            for hash in hashes {
+                if let Some(heights) = self.tx_heights.get_mut(&hash) {
+                    heights.remove(&h);
+                    if heights.is_empty() {
+                        self.tx_heights.remove(&hash);
+                    }
+                }
             }
         }
+    }
+
+    fn check_tx_hash(&self, tx_hash: &[u8]) -> Result<(), RejectReason> {
+        if self.tx_heights.contains_key(tx_hash) {
+            return Err(RejectReason::DupTransaction);
+        }
         Ok(())
     }
 
-    fn check_transaction(&self, tx: &Transaction) -> Result<(), String> {
+    fn check_transaction(&self, tx: &Transaction) -> Result<(), RejectReason> {
         if tx.version != self.sys_config.version {
-            return Err("Invalid version".to_owned());
+            return Err(RejectReason::InvalidVersion);
         }
         if tx.to.len() != 20 && !tx.to.is_empty() {
-            return Err("Invalid to".to_owned());
+            return Err(RejectReason::InvalidTo);
         }
         if tx.nonce.len() > 128 {
-            return Err("Invalid nonce".to_owned());
+            return Err(RejectReason::InvalidNonce);
         }
         if tx.valid_until_block <= self.current_block_number
-            || tx.valid_until_block > (self.current_block_number + BLOCKLIMIT)
+            || tx.valid_until_block > (self.current_block_number + self.sys_config.block_limit)
         {
-            return Err("Invalid valid_until_block".to_owned());
+            return Err(RejectReason::InvalidValidUntilBlock);
         }
         if tx.value.len() != 32 {
-            return Err("Invalid value".to_owned());
+            return Err(RejectReason::InvalidValue);
         }
         if tx.chain_id.len() != 32 || tx.chain_id != self.sys_config.chain_id {
-            return Err("Invalid chain_id".to_owned());
+            return Err(RejectReason::InvalidChainId);
         }
         Ok(())
     }
 
-    fn check_utxo_transaction(&self, utxo_tx: &UtxoTransaction) -> Result<(), String> {
+    fn check_utxo_transaction(&self, utxo_tx: &UtxoTransaction) -> Result<(), RejectReason> {
         if utxo_tx.version != self.sys_config.version {
-            return Err("Invalid version".to_owned());
+            return Err(RejectReason::InvalidVersion);
         }
-        let lock_id = utxo_tx.lock_id;
+        let lock_id = utxo_tx.lock_id & !LOCK_ID_SCHEDULED_FLAG;
         if !(LOCK_ID_VERSION..LOCK_ID_BUTTON).contains(&lock_id) {
-            return Err("Invalid lock_id".to_owned());
+            return Err(RejectReason::InvalidLockId);
+        }
+        let output = if utxo_tx.lock_id & LOCK_ID_SCHEDULED_FLAG != 0 {
+            match decode_scheduled(&utxo_tx.output) {
+                Some((activation_height, output))
+                    if activation_height > self.current_block_number =>
+                {
+                    output
+                }
+                _ => return Err(RejectReason::InvalidActivationHeight),
+            }
+        } else {
+            utxo_tx.output.clone()
+        };
+        if self.sys_config.pending_changes.contains_key(&lock_id) {
+            return Err(RejectReason::ChangePending);
+        }
+        if self.sys_config.check_output(lock_id, &output).is_err() {
+            return Err(RejectReason::InvalidOutput);
         }
         let hash = self.sys_config.utxo_tx_hashes.get(&lock_id).unwrap();
         if hash != &utxo_tx.pre_tx_hash {
-            return Err("Invalid pre_tx_hash".to_owned());
+            return Err(RejectReason::InvalidPreTxHash);
         }
         Ok(())
     }
 
-    pub async fn check_raw_tx(&self, raw_tx: RawTransaction) -> Result<Vec<u8>, String> {
-        if let Some(tx) = raw_tx.tx {
-            match tx {
-                NormalTx(normal_tx) => {
-                    if normal_tx.witness.is_none() {
-                        return Err("witness is none".to_owned());
-                    }
-
-                    let witness = normal_tx.witness.unwrap();
-                    let signature = witness.signature;
-                    let sender = witness.sender;
+    pub async fn check_raw_tx(&self, raw_tx: RawTransaction) -> Result<Vec<u8>, RejectReason> {
+        self.precheck_raw_tx(&raw_tx)?;
+        verify_raw_tx(&raw_tx)
+    }
 
-                    if self.sys_config.emergency_brake {
-                        return Err("forbidden".to_owned());
-                    }
+    /// Check raw tx against chain state, crypto part is left to verify_raw_tx.
+    pub fn precheck_raw_tx(&self, raw_tx: &RawTransaction) -> Result<(), RejectReason> {
+        match raw_tx.tx {
+            Some(NormalTx(ref normal_tx)) => {
+                if normal_tx.witness.is_none() {
+                    return Err(RejectReason::InvalidRawTx);
+                }
 
-                    let mut tx_bytes: Vec<u8> = Vec::new();
-                    if let Some(tx) = normal_tx.transaction {
-                        self.check_transaction(&tx)?;
-                        let ret = tx.encode(&mut tx_bytes);
-                        if ret.is_err() {
-                            return Err("encode tx failed".to_owned());
-                        }
-                    } else {
-                        return Err("tx is none".to_owned());
-                    }
+                let tx = normal_tx
+                    .transaction
+                    .as_ref()
+                    .ok_or(RejectReason::InvalidRawTx)?;
+                let sender = &normal_tx.witness.as_ref().unwrap().sender;
+                if !self.sys_config.is_brake_permitted(sender, tx) {
+                    return Err(RejectReason::EmergencyBrake);
+                }
 
-                    let tx_hash = normal_tx.transaction_hash;
+                if !self.sys_config.is_sender_permitted(sender) {
+                    return Err(RejectReason::SenderNotPermitted);
+                }
 
-                    self.check_tx_hash(&tx_hash)?;
+                self.check_transaction(tx)?;
 
-                    verify_tx_hash(&tx_hash, &tx_bytes).map_err(|e| e.to_string())?;
+                self.check_tx_hash(&normal_tx.transaction_hash)
+            }
+            Some(UtxoTx(ref utxo_tx)) => {
+                let witnesses = &utxo_tx.witnesses;
+                let (admins, threshold) = self.sys_config.admin_committee();
 
-                    if verify_tx_signature(&tx_hash, &signature).map_err(|e| e.to_string())?
-                        == sender
-                    {
-                        Ok(tx_hash)
-                    } else {
-                        Err("Invalid sender".to_owned())
+                // at least threshold admins, each signs once
+                if witnesses.is_empty() || witnesses.len() < threshold as usize {
+                    return Err(RejectReason::InvalidWitnesses);
+                }
+                let mut signers = HashSet::new();
+                for w in witnesses.iter() {
+                    // only admin can send utxo tx
+                    if !admins.contains(&w.sender) {
+                        return Err(RejectReason::NotAdmin);
+                    }
+                    if !signers.insert(&w.sender) {
+                        return Err(RejectReason::InvalidWitnesses);
                     }
                 }
-                UtxoTx(utxo_tx) => {
-                    let witnesses = utxo_tx.witnesses;
 
-                    // limit witnesses length is 1
-                    if witnesses.len() != 1 {
-                        return Err("invalid witnesses".to_owned());
-                    }
+                if let Some(ref tx) = utxo_tx.transaction {
+                    self.check_utxo_transaction(tx)
+                } else {
+                    Err(RejectReason::InvalidRawTx)
+                }
+            }
+            None => Err(RejectReason::InvalidRawTx),
+        }
+    }
+}
 
-                    // only admin can send utxo tx
-                    if witnesses[0].sender != self.sys_config.admin {
-                        return Err("forbidden".to_owned());
-                    }
+/// Verify tx hash and signatures of raw tx, which passed precheck_raw_tx.
+/// It needs no chain state, so can run without holding auth.
+pub fn verify_raw_tx(raw_tx: &RawTransaction) -> Result<Vec<u8>, RejectReason> {
+    match raw_tx.tx {
+        Some(NormalTx(ref normal_tx)) => {
+            let witness = normal_tx
+                .witness
+                .as_ref()
+                .ok_or(RejectReason::InvalidRawTx)?;
+            let tx = normal_tx
+                .transaction
+                .as_ref()
+                .ok_or(RejectReason::InvalidRawTx)?;
 
-                    let mut tx_bytes: Vec<u8> = Vec::new();
-                    if let Some(tx) = utxo_tx.transaction {
-                        self.check_utxo_transaction(&tx)?;
-                        let ret = tx.encode(&mut tx_bytes);
-                        if ret.is_err() {
-                            return Err("encode utxo tx failed".to_owned());
-                        }
-                    } else {
-                        return Err("utxo tx is none".to_owned());
-                    }
+            let mut tx_bytes: Vec<u8> = Vec::new();
+            if tx.encode(&mut tx_bytes).is_err() {
+                return Err(RejectReason::EncodeError);
+            }
 
-                    let tx_hash = utxo_tx.transaction_hash;
-                    verify_tx_hash(&tx_hash, &tx_bytes).map_err(|e| e.to_string())?;
+            let tx_hash = &normal_tx.transaction_hash;
+            verify_tx_hash(tx_hash, &tx_bytes).map_err(|_| RejectReason::InvalidHash)?;
 
-                    for (i, w) in witnesses.into_iter().enumerate() {
-                        let signature = w.signature;
-                        let sender = w.sender;
+            if verify_tx_signature(tx_hash, &witness.signature)
+                .map_err(|_| RejectReason::InvalidSignature)?
+                == witness.sender
+            {
+                Ok(tx_hash.clone())
+            } else {
+                Err(RejectReason::InvalidSender)
+            }
+        }
+        Some(UtxoTx(ref utxo_tx)) => {
+            let tx = utxo_tx
+                .transaction
+                .as_ref()
+                .ok_or(RejectReason::InvalidRawTx)?;
 
-                        if verify_tx_signature(&tx_hash, &signature).map_err(|e| e.to_string())?
-                            != sender
-                        {
-                            return Err(format!("Invalid sender index: {}", i));
-                        }
-                    }
-                    Ok(tx_hash)
+            let mut tx_bytes: Vec<u8> = Vec::new();
+            if tx.encode(&mut tx_bytes).is_err() {
+                return Err(RejectReason::EncodeError);
+            }
+
+            let tx_hash = &utxo_tx.transaction_hash;
+            verify_tx_hash(tx_hash, &tx_bytes).map_err(|_| RejectReason::InvalidHash)?;
+
+            for w in utxo_tx.witnesses.iter() {
+                if verify_tx_signature(tx_hash, &w.signature)
+                    .map_err(|_| RejectReason::InvalidSignature)?
+                    != w.sender
+                {
+                    return Err(RejectReason::InvalidSender);
                 }
             }
-        } else {
-            Err("Invalid raw tx".to_owned())
+            Ok(tx_hash.clone())
+        }
+        None => Err(RejectReason::InvalidRawTx),
+    }
+}
+
+/// Run verify_raw_tx over a batch on the blocking thread pool, chunked by cpu count.
+/// Return the result of each tx in batch order.
+pub async fn batch_verify_each(
+    raw_txs: Vec<RawTransaction>,
+) -> Result<Vec<Result<Vec<u8>, RejectReason>>, Error> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = std::cmp::max(raw_txs.len().div_ceil(threads), MIN_VERIFY_CHUNK_SIZE);
+    if raw_txs.len() <= chunk_size {
+        return Ok(raw_txs.iter().map(verify_raw_tx).collect());
+    }
+
+    let mut handles = Vec::new();
+    let mut rest = raw_txs;
+    while !rest.is_empty() {
+        let tail = rest.split_off(std::cmp::min(chunk_size, rest.len()));
+        let chunk = std::mem::replace(&mut rest, tail);
+        handles.push(tokio::task::spawn_blocking(move || {
+            chunk.iter().map(verify_raw_tx).collect::<Vec<_>>()
+        }));
+    }
+
+    let mut results = Vec::new();
+    for handle in handles {
+        let chunk_results = handle
+            .await
+            .map_err(|e| Error::ExpectError(format!("verify task failed: {}", e)))?;
+        results.extend(chunk_results);
+    }
+    Ok(results)
+}
+
+/// Return tx hashes of a batch in batch order, or the error of the first bad tx in batch order.
+pub async fn batch_verify_raw_txs(raw_txs: Vec<RawTransaction>) -> Result<Vec<Vec<u8>>, Error> {
+    batch_verify_each(raw_txs)
+        .await?
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .map_err(Error::TxRejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        batch_verify_each, batch_verify_raw_txs, history_window, Authentication,
+        MIN_VERIFY_CHUNK_SIZE,
+    };
+    use crate::error::{Error, RejectReason};
+    use crate::util::init_test_crypto;
+    use crate::utxo_set::{
+        BrakeMode, SystemConfig, DEFAULT_BLOCK_LIMIT as BLOCKLIMIT, LOCK_ID_BLOCK_INTERVAL,
+        LOCK_ID_SCHEDULED_FLAG, LOCK_ID_VERSION,
+    };
+    use cita_cloud_proto::blockchain::raw_transaction::Tx;
+    use cita_cloud_proto::blockchain::{
+        RawTransaction, Transaction, UnverifiedTransaction, UnverifiedUtxoTransaction,
+        UtxoTransaction, Witness,
+    };
+    use std::collections::BTreeSet;
+
+    // tx fails on hash check before its signature is verified
+    fn bad_hash_tx(hash_len: usize) -> RawTransaction {
+        RawTransaction {
+            tx: Some(Tx::NormalTx(UnverifiedTransaction {
+                transaction: Some(Transaction::default()),
+                transaction_hash: vec![0xff; hash_len],
+                witness: Some(Witness::default()),
+            })),
         }
     }
+
+    fn none_tx() -> RawTransaction {
+        RawTransaction { tx: None }
+    }
+
+    #[tokio::test]
+    async fn batch_verify_order_test() {
+        init_test_crypto();
+        let len = MIN_VERIFY_CHUNK_SIZE * 16;
+
+        // the first bad tx in batch order wins, whichever chunk finishes first
+        let mut raw_txs = vec![bad_hash_tx(32); len];
+        raw_txs[len - 1] = none_tx();
+        assert!(matches!(
+            batch_verify_raw_txs(raw_txs).await,
+            Err(Error::TxRejected(RejectReason::InvalidHash))
+        ));
+
+        let mut raw_txs = vec![bad_hash_tx(32); len];
+        raw_txs[0] = none_tx();
+        assert!(matches!(
+            batch_verify_raw_txs(raw_txs).await,
+            Err(Error::TxRejected(RejectReason::InvalidRawTx))
+        ));
+
+        // small batch is verified inline
+        assert!(matches!(
+            batch_verify_raw_txs(vec![bad_hash_tx(31)]).await,
+            Err(Error::TxRejected(RejectReason::InvalidHash))
+        ));
+        assert!(matches!(batch_verify_raw_txs(vec![]).await, Ok(hashes) if hashes.is_empty()));
+
+        // each tx gets its own result
+        let mut raw_txs = vec![bad_hash_tx(32); len];
+        raw_txs[len / 2] = none_tx();
+        let results = batch_verify_each(raw_txs).await.unwrap();
+        assert_eq!(results.len(), len);
+        assert_eq!(results[len / 2], Err(RejectReason::InvalidRawTx));
+        assert_eq!(results[len - 1], Err(RejectReason::InvalidHash));
+    }
+
+    fn auth() -> Authentication {
+        Authentication::new(SystemConfig::new(0, vec![], vec![], 3, vec![]))
+    }
+
+    fn insert_blocks(auth: &mut Authentication, heights: std::ops::RangeInclusive<u64>) {
+        for h in heights {
+            auth.insert_tx_hash(h, vec![vec![(h % 256) as u8, (h / 256) as u8]]);
+        }
+    }
+
+    #[test]
+    fn dup_eviction_test() {
+        let mut auth = auth();
+        let tx_hash = vec![0xff; 32];
+        auth.insert_tx_hash(1, vec![tx_hash.clone()]);
+        insert_blocks(&mut auth, 2..=BLOCKLIMIT);
+
+        // h - BLOCKLIMIT is 0, block 1 still in history
+        assert_eq!(
+            auth.check_tx_hash(&tx_hash),
+            Err(RejectReason::DupTransaction)
+        );
+        assert!(auth.check_tx_hash(&[BLOCKLIMIT as u8, 0]).is_err());
+
+        // block 1 evicted at h = 1 + BLOCKLIMIT
+        insert_blocks(&mut auth, BLOCKLIMIT + 1..=BLOCKLIMIT + 1);
+        assert!(auth.check_tx_hash(&tx_hash).is_ok());
+        assert!(auth.check_tx_hash(&[1, 0]).is_ok());
+        assert!(auth.check_tx_hash(&[2, 0]).is_err());
+        assert_eq!(auth.history_hashes.len(), BLOCKLIMIT as usize);
+        assert_eq!(auth.tx_heights.len(), BLOCKLIMIT as usize);
+    }
+
+    #[test]
+    fn dup_in_two_blocks_test() {
+        let mut auth = auth();
+        let tx_hash = vec![0xff; 32];
+        auth.insert_tx_hash(1, vec![tx_hash.clone()]);
+        auth.insert_tx_hash(5, vec![tx_hash.clone()]);
+        insert_blocks(&mut auth, 6..=BLOCKLIMIT + 1);
+
+        // still in block 5 after block 1 evicted
+        assert!(auth.check_tx_hash(&tx_hash).is_err());
+        assert_eq!(auth.tx_heights[&tx_hash], vec![5].into_iter().collect());
+        insert_blocks(&mut auth, BLOCKLIMIT + 2..=BLOCKLIMIT + 5);
+        assert!(auth.check_tx_hash(&tx_hash).is_ok());
+    }
+
+    #[test]
+    fn reinsert_height_test() {
+        let mut auth = auth();
+        auth.insert_tx_hash(1, vec![vec![1; 32]]);
+        auth.insert_tx_hash(1, vec![vec![2; 32]]);
+        assert!(auth.check_tx_hash(&[1; 32]).is_ok());
+        assert!(auth.check_tx_hash(&[2; 32]).is_err());
+    }
+
+    #[test]
+    fn block_limit_change_test() {
+        let mut auth = auth();
+        insert_blocks(&mut auth, 1..=10);
+
+        // decreased in block 11, txs of 1..=11 were checked with the old limit
+        auth.sys_config.block_limit = 5;
+        insert_blocks(&mut auth, 11..=17);
+        assert!(auth.check_tx_hash(&[12, 0]).is_ok());
+        assert!(auth.check_tx_hash(&[13, 0]).is_err());
+        assert!(auth.check_tx_hash(&[1, 0]).is_err());
+        assert!(auth.check_tx_hash(&[11, 0]).is_err());
+        insert_blocks(&mut auth, 18..=BLOCKLIMIT + 11);
+        assert!(auth.check_tx_hash(&[11, 0]).is_ok());
+        assert_eq!(auth.history_hashes.len(), 5);
+        assert_eq!(auth.history_evictions.len(), 5);
+
+        // increased, blocks before keep their own evict height
+        let h = BLOCKLIMIT + 11;
+        auth.sys_config.block_limit = 10;
+        insert_blocks(&mut auth, h + 1..=h + 1);
+        assert_eq!(auth.history_hashes.len(), 5);
+        insert_blocks(&mut auth, h + 2..=h + 20);
+        assert_eq!(auth.history_hashes.len(), 10);
+
+        let tx = Transaction {
+            valid_until_block: h + 20 + 11,
+            value: vec![0; 32],
+            ..Default::default()
+        };
+        assert_eq!(
+            auth.check_transaction(&tx),
+            Err(RejectReason::InvalidValidUntilBlock)
+        );
+        // passes valid_until_block check
+        let tx = Transaction {
+            valid_until_block: h + 20 + 10,
+            ..tx
+        };
+        assert_eq!(
+            auth.check_transaction(&tx),
+            Err(RejectReason::InvalidChainId)
+        );
+    }
+
+    #[test]
+    fn restart_block_limit_test() {
+        let mut sys_config = SystemConfig::new(0, vec![], vec![], 3, vec![]);
+        sys_config.block_limit = 300;
+        let mut auth = Authentication::new(sys_config);
+        insert_blocks(&mut auth, 1..=399);
+
+        // lowered in block 400 then raised in block 520, history rebuilt on restart at
+        // any height is the one kept by insert_tx_hash
+        let mut changes = vec![];
+        for h in 400..=900 {
+            let block_limit = match h {
+                400 => Some(100),
+                520 => Some(200),
+                _ => None,
+            };
+            if let Some(block_limit) = block_limit {
+                auth.sys_config.block_limit = block_limit;
+                changes.push((h, block_limit));
+            }
+            insert_blocks(&mut auth, h..=h);
+            let window: BTreeSet<(u64, u64)> = history_window(h, 300, &changes)
+                .into_iter()
+                .map(|(height, evict_height)| (evict_height, height))
+                .collect();
+            assert_eq!(window, auth.history_evictions);
+        }
+
+        // blocks checked with 300 are kept after lowered
+        assert!(history_window(450, 300, &[(400, 100)]).contains(&(300, 600)));
+        assert_eq!(history_window(450, 300, &[(400, 100)]).len(), 300);
+        // the later of changes in one block
+        assert_eq!(
+            history_window(7, 3, &[(5, 1), (5, 2)]),
+            vec![(5, 8), (6, 8), (7, 9)]
+        );
+        assert!(history_window(0, 100, &[]).is_empty());
+    }
+
+    #[test]
+    fn sender_permission_test() {
+        let mut sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        sys_config.sender_denylist.insert(vec![1; 20]);
+        let auth = Authentication::new(sys_config);
+
+        let mut raw_tx = bad_hash_tx(32);
+        if let Some(Tx::NormalTx(ref mut normal_tx)) = raw_tx.tx {
+            normal_tx.witness.as_mut().unwrap().sender = vec![1; 20];
+        }
+        assert_eq!(
+            auth.precheck_raw_tx(&raw_tx),
+            Err(RejectReason::SenderNotPermitted)
+        );
+    }
+
+    #[test]
+    fn brake_mode_test() {
+        let raw_tx = |sender: u8, to: Vec<u8>| RawTransaction {
+            tx: Some(Tx::NormalTx(UnverifiedTransaction {
+                transaction: Some(Transaction {
+                    to,
+                    ..Default::default()
+                }),
+                transaction_hash: vec![0xff; 32],
+                witness: Some(Witness {
+                    sender: vec![sender; 20],
+                    ..Default::default()
+                }),
+            })),
+        };
+        let auth = |mode: BrakeMode| {
+            let mut sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+            sys_config.brake_mode = mode;
+            Authentication::new(sys_config)
+        };
+        // passing the brake fails later on the tx itself
+        let passed = Err(RejectReason::InvalidValidUntilBlock);
+        let braked = Err(RejectReason::EmergencyBrake);
+
+        let call = raw_tx(1, vec![2; 20]);
+        let create = raw_tx(1, vec![]);
+        let cases = vec![
+            (BrakeMode::Off, &call, passed),
+            (BrakeMode::Off, &create, passed),
+            (BrakeMode::All, &call, braked),
+            (BrakeMode::All, &create, braked),
+            (BrakeMode::ContractCreation, &call, passed),
+            (BrakeMode::ContractCreation, &create, braked),
+            (
+                BrakeMode::Senders(vec![vec![1; 20]].into_iter().collect()),
+                &create,
+                passed,
+            ),
+            (
+                BrakeMode::Senders(vec![vec![3; 20]].into_iter().collect()),
+                &call,
+                braked,
+            ),
+        ];
+        for (mode, raw_tx, expected) in cases {
+            assert_eq!(
+                auth(mode.clone()).precheck_raw_tx(raw_tx),
+                expected,
+                "{:?}",
+                mode
+            );
+        }
+
+        // emergency_brake rejects all whatever the mode
+        let mut sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        sys_config.emergency_brake = true;
+        let auth = Authentication::new(sys_config);
+        assert_eq!(auth.precheck_raw_tx(&call), braked);
+    }
+
+    fn utxo_tx(signers: &[u8]) -> RawTransaction {
+        RawTransaction {
+            tx: Some(Tx::UtxoTx(UnverifiedUtxoTransaction {
+                transaction: Some(UtxoTransaction {
+                    lock_id: LOCK_ID_VERSION,
+                    output: 1u32.to_be_bytes().to_vec(),
+                    pre_tx_hash: vec![0u8; 33],
+                    ..Default::default()
+                }),
+                transaction_hash: vec![0u8; 32],
+                witnesses: signers
+                    .iter()
+                    .map(|signer| Witness {
+                        sender: vec![*signer; 20],
+                        ..Default::default()
+                    })
+                    .collect(),
+            })),
+        }
+    }
+
+    #[test]
+    fn admin_committee_test() {
+        let mut sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        let auth = Authentication::new(sys_config.clone());
+        assert!(auth.precheck_raw_tx(&utxo_tx(&[9])).is_ok());
+        assert_eq!(
+            auth.precheck_raw_tx(&utxo_tx(&[1])),
+            Err(RejectReason::NotAdmin)
+        );
+        assert_eq!(
+            auth.precheck_raw_tx(&utxo_tx(&[])),
+            Err(RejectReason::InvalidWitnesses)
+        );
+
+        // 2 of 3
+        sys_config.admins = vec![vec![1; 20], vec![2; 20], vec![3; 20]];
+        sys_config.admin_threshold = 2;
+        let auth = Authentication::new(sys_config);
+        assert!(auth.precheck_raw_tx(&utxo_tx(&[1, 3])).is_ok());
+        assert!(auth.precheck_raw_tx(&utxo_tx(&[3, 2, 1])).is_ok());
+        assert_eq!(
+            auth.precheck_raw_tx(&utxo_tx(&[1])),
+            Err(RejectReason::InvalidWitnesses)
+        );
+        assert_eq!(
+            auth.precheck_raw_tx(&utxo_tx(&[1, 1])),
+            Err(RejectReason::InvalidWitnesses)
+        );
+        assert_eq!(
+            auth.precheck_raw_tx(&utxo_tx(&[1, 9])),
+            Err(RejectReason::NotAdmin)
+        );
+        assert!(auth.precheck_raw_tx(&utxo_tx(&[9])).is_err());
+    }
+
+    #[test]
+    fn scheduled_change_test() {
+        let mut auth = auth();
+        insert_blocks(&mut auth, 1..=5);
+        let scheduled_tx = |activation_height: u64| {
+            let mut output = activation_height.to_be_bytes().to_vec();
+            output.extend_from_slice(&6u32.to_be_bytes());
+            UtxoTransaction {
+                lock_id: LOCK_ID_BLOCK_INTERVAL | LOCK_ID_SCHEDULED_FLAG,
+                output,
+                pre_tx_hash: vec![0u8; 33],
+                ..Default::default()
+            }
+        };
+
+        assert_eq!(
+            auth.check_utxo_transaction(&scheduled_tx(5)),
+            Err(RejectReason::InvalidActivationHeight)
+        );
+        let mut tx = scheduled_tx(6);
+        tx.output.truncate(7);
+        assert_eq!(
+            auth.check_utxo_transaction(&tx),
+            Err(RejectReason::InvalidActivationHeight)
+        );
+        assert_eq!(auth.check_utxo_transaction(&scheduled_tx(6)), Ok(()));
+        let mut tx = scheduled_tx(6);
+        tx.output.truncate(10);
+        assert_eq!(
+            auth.check_utxo_transaction(&tx),
+            Err(RejectReason::InvalidOutput)
+        );
+
+        // no other change of the lock_id till activated
+        assert!(auth.sys_config.update(
+            &UnverifiedUtxoTransaction {
+                transaction: Some(scheduled_tx(6)),
+                transaction_hash: vec![1u8; 32],
+                witnesses: vec![],
+            },
+            false
+        ));
+        let tx = UtxoTransaction {
+            lock_id: LOCK_ID_BLOCK_INTERVAL,
+            output: vec![0, 0, 0, 9],
+            pre_tx_hash: vec![1u8; 32],
+            ..Default::default()
+        };
+        assert_eq!(
+            auth.check_utxo_transaction(&tx),
+            Err(RejectReason::ChangePending)
+        );
+        assert_eq!(auth.sys_config.activate(6).len(), 1);
+        assert_eq!(auth.sys_config.block_interval, 6);
+        assert_eq!(auth.check_utxo_transaction(&tx), Ok(()));
+    }
 }
@@ -12,13 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::auth::Authentication;
+use crate::auth::{batch_verify_raw_txs, Authentication};
 use crate::error::Error;
+use crate::genesis::GenesisBlock;
+use crate::journal::Journal;
+use crate::merkle::{transactions_root, BLOCK_VERSION_MERKLE};
 use crate::node_manager::ChainStatus;
 use crate::pool::Pool;
+use crate::protocol::controller_storage::{FinalizingBlock, StorageWrite};
 use crate::util::*;
-use crate::utxo_set::SystemConfig;
-use crate::GenesisBlock;
+use crate::utxo_set::{
+    decode_scheduled, encode_history_record, SystemConfig, LOCK_ID_BLOCK_LIMIT, LOCK_ID_BUTTON,
+    LOCK_ID_HISTORY_FLAG, LOCK_ID_SCHEDULED_FLAG, LOCK_ID_SENDER_ALLOWLIST,
+    LOCK_ID_SENDER_DENYLIST, LOCK_ID_VERSION,
+};
 use cita_cloud_proto::blockchain::raw_transaction::Tx;
 use cita_cloud_proto::blockchain::{Block, BlockHeader, RawTransaction, RawTransactions};
 use cita_cloud_proto::common::{
@@ -26,7 +33,7 @@ use cita_cloud_proto::common::{
 };
 use log::{info, warn};
 use prost::Message;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
@@ -34,6 +41,9 @@ use tokio::time;
 
 const FORCE_IN_SYNC: u64 = 6;
 
+// ms a block timestamp may be ahead of local time, for clock skew between nodes
+const MAX_TIMESTAMP_DRIFT: u64 = 10_000;
+
 #[derive(PartialEq)]
 pub enum ChainStep {
     SyncStep,
@@ -45,6 +55,8 @@ pub struct Chain {
     block_number: u64,
     block_hash: Vec<u8>,
     block_delay_number: u32,
+    // blocks from this height commit to merkle root of tx hashes
+    merkle_activation_height: u64,
     // hashmap for each index
     // key of hashmap is block_hash
     // value of hashmap is (block, proof)
@@ -57,32 +69,43 @@ pub struct Chain {
     // todo auth set in controller not chain
     auth: Arc<RwLock<Authentication>>,
     genesis: GenesisBlock,
+    // height - validators in force after the block, from the last change at or before
+    // block_number - block_delay_number on
+    validator_history: BTreeMap<u64, Vec<Vec<u8>>>,
     key_id: u64,
     node_address: Vec<u8>,
+    // blocks of main_chain with their proofs, rebuilt from on restart
+    journal: Option<Journal>,
 }
 
 impl Chain {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         block_delay_number: u32,
+        merkle_activation_height: u64,
         current_block_number: u64,
         current_block_hash: Vec<u8>,
         pool: Arc<RwLock<Pool>>,
         auth: Arc<RwLock<Authentication>>,
         genesis: GenesisBlock,
+        validators: Vec<Vec<u8>>,
         key_id: u64,
         node_address: Vec<u8>,
+        journal: Option<Journal>,
     ) -> Self {
         let fork_tree_size = (block_delay_number * 2 + 2) as usize;
         let mut fork_tree = Vec::with_capacity(fork_tree_size);
         for _ in 0..=fork_tree_size {
             fork_tree.push(HashMap::new());
         }
+        let mut validator_history = BTreeMap::new();
+        validator_history.insert(current_block_number, validators);
 
         Chain {
             block_number: current_block_number,
             block_hash: current_block_hash,
             block_delay_number,
+            merkle_activation_height,
             fork_tree,
             main_chain: Vec::new(),
             main_chain_tx_hash: Vec::new(),
@@ -90,12 +113,14 @@ impl Chain {
             pool,
             auth,
             genesis,
+            validator_history,
             key_id,
             node_address,
+            journal,
         }
     }
 
-    pub async fn init(&self, init_block_number: u64) {
+    pub async fn init(&mut self, init_block_number: u64) {
         if init_block_number == 0 {
             info!("finalize genesis block");
             let mut interval = time::interval(Duration::from_secs(3));
@@ -114,9 +139,75 @@ impl Chain {
         }
     }
 
-    pub async fn init_auth(&self, init_block_number: u64) {
+    // rebuild main_chain committed before restart, stops at the first block not following it
+    pub fn restore_main_chain(&mut self) {
+        let blocks: Vec<Block> = match self.journal.as_ref() {
+            Some(journal) => journal.load().unwrap_or_else(|e| {
+                warn!("load chain journal failed: {}", e);
+                Vec::new()
+            }),
+            None => return,
+        };
+        let total = blocks.len();
+
+        for block in blocks {
+            let height = match block.header.as_ref() {
+                Some(header) => header.height,
+                None => break,
+            };
+            // finalized meanwhile
+            if height <= self.block_number {
+                continue;
+            }
+            let index = self.main_chain.len();
+            let prevhash = self.main_chain.last().unwrap_or(&self.block_hash);
+            if index >= self.block_delay_number as usize
+                || height != self.block_number + index as u64 + 1
+                || &block.header.as_ref().unwrap().prevhash != prevhash
+                || block.proof.is_empty()
+            {
+                warn!(
+                    "restore_main_chain: block({}) doesn't follow main_chain",
+                    height
+                );
+                break;
+            }
+            let block_hash = match get_block_hash(block.header.as_ref()) {
+                Ok(block_hash) => block_hash,
+                Err(_) => break,
+            };
+            let tx_hashes = full_to_compact(block.clone()).body.unwrap().tx_hashes;
+            self.main_chain_tx_hash.extend_from_slice(&tx_hashes);
+            self.main_chain.push(block_hash.clone());
+            self.fork_tree[index].insert(block_hash, block);
+        }
+        info!(
+            "restore_main_chain: restore {} of {} blocks",
+            self.main_chain.len(),
+            total
+        );
+        print_main_chain(&self.main_chain, self.block_number);
+        self.save_main_chain();
+    }
+
+    // replace the journal with blocks of main_chain
+    fn save_main_chain(&mut self) {
+        let fork_tree = &self.fork_tree;
+        if let Some(journal) = self.journal.as_mut() {
+            let blocks = self
+                .main_chain
+                .iter()
+                .enumerate()
+                .filter_map(|(index, block_hash)| fork_tree[index].get(block_hash));
+            if let Err(e) = journal.rewrite(blocks) {
+                warn!("rewrite chain journal failed: {}", e);
+            }
+        }
+    }
+
+    pub async fn init_auth(&self, init_block_number: u64, init_block_limit: u64) {
         let mut auth = self.auth.write().await;
-        auth.init(init_block_number).await;
+        auth.init(init_block_number, init_block_limit).await;
     }
 
     pub fn get_block_number(&self, is_pending: bool) -> u64 {
@@ -189,6 +280,7 @@ impl Chain {
 
         if !self.fork_tree[(block_height - self.block_number - 1) as usize].contains_key(block_hash)
         {
+            self.validate_header(&block).await?;
             self.fork_tree[(block_height - self.block_number - 1) as usize]
                 .insert(block_hash.to_vec(), block);
             Ok(true)
@@ -219,18 +311,18 @@ impl Chain {
                 pool.package(self.block_number + 1)
             };
 
-            let mut data = Vec::new();
-            for hash in tx_hash_list.iter() {
-                data.extend_from_slice(hash);
-            }
-            let transactions_root = hash_data(&data);
-
             let prevhash = if self.main_chain.is_empty() {
                 self.block_hash.clone()
             } else {
                 self.main_chain.last().unwrap().to_owned()
             };
             let height = self.block_number + self.main_chain.len() as u64 + 1;
+            let version = if height >= self.merkle_activation_height {
+                BLOCK_VERSION_MERKLE
+            } else {
+                0
+            };
+            let transactions_root = transactions_root(version, &tx_hash_list);
 
             info!("proposal {} prevhash 0x{}", height, hex::encode(&prevhash));
             let header = BlockHeader {
@@ -242,7 +334,7 @@ impl Chain {
             };
 
             let full_block = Block {
-                version: 0,
+                version,
                 header: Some(header.clone()),
                 body: Some(RawTransactions { body: tx_list }),
                 proof: vec![],
@@ -283,6 +375,20 @@ impl Chain {
 
         match proposal.proposal {
             Some(Proposal::BftProposal(bft_proposal)) => {
+                if let Some(body) = bft_proposal.proposal.as_ref().and_then(|b| b.body.as_ref()) {
+                    let package_limit = self.pool.read().await.package_limit();
+                    package_limit.check(body).map_err(|e| {
+                        warn!("check_proposal(h: {}) failed: {}", h, e);
+                        e
+                    })?;
+                }
+                if let Some(block) = bft_proposal.proposal.as_ref() {
+                    self.validate_header(block).await.map_err(|e| {
+                        warn!("check_proposal(h: {}) failed: {}", h, e);
+                        e
+                    })?;
+                }
+
                 let pre_h = h - self.block_delay_number as u64 - 1;
                 let key = pre_h.to_be_bytes().to_vec();
 
@@ -309,32 +415,44 @@ impl Chain {
         }
     }
 
-    async fn finalize_block(&self, block: Block, block_hash: Vec<u8>) -> Result<(), Error> {
-        // region 1: tx_hash - tx
+    async fn finalize_block(&mut self, block: Block, block_hash: Vec<u8>) -> Result<(), Error> {
+        let mut sender_lists_changed = false;
+        let mut block_limit_changed = false;
+        let block_height = block.header.as_ref().ok_or(Error::NoneBlockHeader)?.height;
+        // region 0 writes of sys_config changes, applied with the block
+        let mut writes = Vec::new();
+        // changed on a copy, in force only after the block is stored
+        let mut sys_config = self.get_system_config().await;
         if let Some(raw_txs) = block.body.clone() {
             for raw_tx in raw_txs.body {
                 match raw_tx.tx.clone() {
                     Some(Tx::UtxoTx(utxo_tx)) => {
-                        if {
-                            let mut auth = self.auth.write().await;
-                            auth.update_system_config(&utxo_tx)
-                        } {
+                        if sys_config.update(&utxo_tx, false) {
                             // if sys_config changed, store utxo tx hash into global region
                             let lock_id = utxo_tx.transaction.as_ref().unwrap().lock_id;
-                            store_data(
-                                0,
-                                lock_id.to_be_bytes().to_vec(),
+                            if lock_id == LOCK_ID_SENDER_ALLOWLIST
+                                || lock_id == LOCK_ID_SENDER_DENYLIST
+                            {
+                                sender_lists_changed = true;
+                            }
+                            if lock_id == LOCK_ID_BLOCK_LIMIT {
+                                block_limit_changed = true;
+                            }
+                            // scheduled change is stored with the flag till activated
+                            writes.push(utxo_tx_hash_write(
+                                lock_id,
                                 utxo_tx.transaction_hash.clone(),
-                            )
-                            .await
-                            .map_err(|e| {
-                                warn!(
-                                    "store utxo(0x{}) failed, error: {}",
-                                    hex::encode(&utxo_tx.transaction_hash),
-                                    e.to_string()
-                                );
-                                Error::StoreError
-                            })?;
+                            ));
+                            if lock_id & LOCK_ID_SCHEDULED_FLAG == 0 {
+                                let write = utxo_history_write(
+                                    lock_id,
+                                    block_height,
+                                    &utxo_tx.transaction_hash,
+                                    &writes,
+                                )
+                                .await?;
+                                writes.extend(write);
+                            }
                         }
                     }
                     _ => {}
@@ -342,80 +460,86 @@ impl Chain {
             }
         }
 
+        for (lock_id, tx_hash, applied) in sys_config.activate(block_height) {
+            // dropped either way, restart must not schedule it again
+            writes.push(utxo_tx_hash_write(lock_id | LOCK_ID_SCHEDULED_FLAG, vec![]));
+            if !applied {
+                warn!(
+                    "drop scheduled change of lock_id {} by utxo(0x{}) at h: {}",
+                    lock_id,
+                    hex::encode(&tx_hash),
+                    block_height
+                );
+                continue;
+            }
+            info!(
+                "activate scheduled change of lock_id {} by utxo(0x{}) at h: {}",
+                lock_id,
+                hex::encode(&tx_hash),
+                block_height
+            );
+            if lock_id == LOCK_ID_SENDER_ALLOWLIST || lock_id == LOCK_ID_SENDER_DENYLIST {
+                sender_lists_changed = true;
+            }
+            if lock_id == LOCK_ID_BLOCK_LIMIT {
+                block_limit_changed = true;
+            }
+            // restart applies it as a normal change
+            let write = utxo_history_write(lock_id, block_height, &tx_hash, &writes).await?;
+            writes.extend(write);
+            writes.push(utxo_tx_hash_write(lock_id, tx_hash));
+        }
+
         let block_bytes = {
             let mut buf = Vec::with_capacity(block.encoded_len());
             block
                 .encode(&mut buf)
-                .map_err(|_| Error::EncodeError(format!("encode Block failed")))?;
+                .map_err(|_| Error::EncodeError("encode Block failed".to_owned()))?;
             buf
         };
-
-        let block_height = block.header.as_ref().ok_or(Error::NoneBlockHeader)?.height;
-        let block_height_bytes = block_height.to_be_bytes().to_vec();
-
-        store_data(11, block_height_bytes.clone(), block_bytes)
-            .await
-            .map_err(|e| {
-                warn!(
-                    "store Block({}) failed, error: {}",
-                    block_height,
-                    e.to_string()
-                );
-                Error::StoreError
-            })?;
-
         let tx_hash_list = get_tx_hash_list(block.body.as_ref().ok_or(Error::NoneBlockBody)?)?;
 
-        // exec bloc
-        let executed_block_hash = exec_block(block).await.map_err(|e| {
-            warn!("exec_block({}) error: {}", block_height, e.to_string());
-            Error::ExecuteError
-        })?;
-        // region 6 : block_height - executed_block_hash
-        store_data(6, block_height_bytes.clone(), executed_block_hash.clone())
-            .await
-            .map_err(|e| {
-                warn!(
-                    "store state_root(0x{}) failed, error: {}",
-                    hex::encode(&executed_block_hash),
-                    e.to_string()
-                );
-                Error::StoreError
-            })?;
+        // nothing of the block is stored before the journal, everything after is replayed from it
+        let finalizing = FinalizingBlock {
+            block: block_bytes,
+            block_hash: block_hash.clone(),
+            writes,
+        };
+        store_finalizing(&finalizing).await?;
+        apply_finalizing(block, &finalizing).await?;
 
         // this must be before update pool
         {
             let mut auth = self.auth.write().await;
+            auth.set_system_config(sys_config.clone());
             auth.insert_tx_hash(block_height, tx_hash_list.clone());
         }
+        if self.validator_history.values().next_back() != Some(&sys_config.validators) {
+            self.validator_history
+                .insert(block_height, sys_config.validators.clone());
+        }
+        let lowest = block_height.saturating_sub(self.block_delay_number as u64);
+        if let Some(&first) = self
+            .validator_history
+            .range(..=lowest)
+            .next_back()
+            .map(|(h, _)| h)
+        {
+            self.validator_history = self.validator_history.split_off(&first);
+        }
         // update pool
         {
             let mut pool = self.pool.write().await;
             pool.update(&tx_hash_list);
+            pool.sweep_expired(block_height);
+            if sender_lists_changed {
+                pool.drop_unpermitted(|sender| sys_config.is_sender_permitted(sender));
+            }
+            if sender_lists_changed || block_limit_changed {
+                pool.set_block_limit(sys_config.block_limit);
+            }
         }
 
-        // region 0: 0 - current height; 1 - current hash
-        store_data(0, 0u64.to_be_bytes().to_vec(), block_height_bytes)
-            .await
-            .map_err(|e| {
-                warn!(
-                    "store current height({}) failed, error: {}",
-                    block_height,
-                    e.to_string()
-                );
-                Error::StoreError
-            })?;
-        store_data(0, 1u64.to_be_bytes().to_vec(), block_hash.clone())
-            .await
-            .map_err(|e| {
-                warn!(
-                    "store current block_hash(0x{}) failed, error: {}",
-                    hex::encode(&block_hash),
-                    e.to_string()
-                );
-                Error::StoreError
-            })?;
-
         info!(
             "finalize_block: {}, block_hash: 0x{}",
             block_height,
@@ -446,147 +570,145 @@ impl Chain {
             Some(Proposal::BftProposal(bft_proposal)) => Ok(bft_proposal),
             None => Err(Error::ExpectError(format!("no proposal found"))),
         }?;
-
-        for (index, _map) in self.fork_tree.iter_mut().enumerate() {
-            // make sure the block in fork_tree
-            if let Some(mut full_block) = bft_proposal.proposal {
-                // store proof
-                full_block.proof = proof.to_vec();
-                let compact_block = full_to_compact(full_block.clone());
-
-                // try to backwards found a candidate_chain
-                let mut candidate_chain = Vec::new();
-                let mut candidate_chain_tx_hash = Vec::new();
-
-                candidate_chain.push(get_block_hash(full_block.header.as_ref())?);
-                candidate_chain_tx_hash.extend_from_slice(&compact_block.body.unwrap().tx_hashes);
-
-                let mut prev_hash = full_block.header.clone().unwrap().prevhash;
-                for i in 0..index {
-                    let map = self.fork_tree.get(index - i - 1).unwrap();
-                    if let Some(prev_full_block) = map.get(&prev_hash) {
-                        if prev_full_block.proof.is_empty() {
-                            warn!("candidate_chain has no proof");
-                            return Err(Error::ExpectError(
-                                "candidate_chain has no proof".to_string(),
-                            ));
-                        }
-                        let prev_compact_block = full_to_compact(prev_full_block.to_owned());
-                        candidate_chain.push(prev_hash.clone());
-                        for hash in prev_compact_block.to_owned().body.unwrap().tx_hashes {
-                            if candidate_chain_tx_hash.contains(&hash) {
-                                // candidate_chain has dup tx, so failed
-                                warn!("candidate_chain has dup tx");
-                                return Err(Error::ExpectError(
-                                    "candidate_chain has dup tx".to_string(),
-                                ));
-                            }
-                        }
-                        candidate_chain_tx_hash.extend_from_slice(
-                            &prev_compact_block.to_owned().body.unwrap().tx_hashes,
-                        );
-                        prev_hash = prev_full_block.to_owned().header.unwrap().prevhash;
-                    } else {
-                        // candidate_chain interrupted, so failed
-                        warn!("candidate_chain interrupted");
-                        return Err(Error::ExpectError(
-                            "candidate_chain interrupted".to_string(),
-                        ));
-                    }
-                }
-
-                if prev_hash != self.block_hash {
-                    warn!("candidate_chain can't fit finalized block");
-                    // break this invalid chain
-                    let blk_hash = candidate_chain.last().unwrap();
-                    self.fork_tree.get_mut(0).unwrap().remove(blk_hash);
+        let mut full_block = bft_proposal.proposal.ok_or(Error::NoneProposal)?;
+        let header = full_block.header.clone().ok_or(Error::NoneBlockHeader)?;
+        if header.height != height {
+            return Err(Error::BlockCheckError);
+        }
+        check_block_version(&full_block, self.merkle_activation_height)?;
+        let block_hash = get_block_hash(Some(&header))?;
+        let index = (height - self.block_number - 1) as usize;
+
+        // keep the block with its own proof, it is finalized with them
+        full_block.proof = proof.to_vec();
+        let compact_block = full_to_compact(full_block.clone());
+
+        // try to backwards found a candidate_chain
+        let mut candidate_chain = vec![block_hash.clone()];
+        let mut candidate_chain_tx_hash = compact_block.body.unwrap().tx_hashes;
+        let mut prev_hash = header.prevhash.clone();
+        let mut timestamp = header.timestamp;
+        for i in (0..index).rev() {
+            let prev_full_block = match self.fork_tree[i].get(&prev_hash) {
+                Some(prev_full_block) => prev_full_block,
+                None => {
+                    // candidate_chain interrupted, so failed
+                    warn!("candidate_chain interrupted");
                     return Err(Error::ExpectError(
-                        "candidate_chain can't fit finalized block".to_string(),
-                    ));
-                }
-
-                // if candidate_chain longer than original main_chain
-                if candidate_chain.len() > self.main_chain.len() {
-                    // replace the main_chain
-                    candidate_chain.reverse();
-                    self.main_chain = candidate_chain;
-                    self.main_chain_tx_hash = candidate_chain_tx_hash;
-                    print_main_chain(&self.main_chain, self.block_number);
-                    // check if any block has been finalized
-                    if self.main_chain.len() > self.block_delay_number as usize {
-                        let finalized_blocks_number =
-                            self.main_chain.len() - self.block_delay_number as usize;
-                        let new_main_chain = self.main_chain.split_off(finalized_blocks_number);
-                        let mut finalized_tx_hash_list = Vec::new();
-                        // save finalized blocks / txs / current height / current hash
-                        for (_index, block_hash) in self.main_chain.iter().enumerate() {
-                            // get block
-                            // let block = self.fork_tree[index].get(block_hash).unwrap().to_owned();
-                            let block = full_block.clone();
-                            self.finalize_block(block.clone(), block_hash.to_owned())
-                                .await?;
-                            let block_body = full_to_compact(block).body.unwrap();
-                            finalized_tx_hash_list
-                                .extend_from_slice(block_body.tx_hashes.as_slice());
-                        }
-                        self.block_number += finalized_blocks_number as u64;
-                        self.block_hash = self.main_chain[finalized_blocks_number - 1].to_owned();
-
-                        // let sys_config = self.get_system_config().await;
-                        // let csf = ChainStatusWithFlag {
-                        //     status: ChainStatus {
-                        //         version: sys_config.version,
-                        //         chain_id: sys_config.chain_id,
-                        //         height: self.block_number,
-                        //         hash: Some(Hash {
-                        //             hash: self.block_hash.clone(),
-                        //         }),
-                        //         address: None,
-                        //     },
-                        //     broadcast_or_not: true,
-                        // };
-                        // self.task_sender.send(EventTask::UpdateStatus(csf)).unwrap();
-
-                        self.main_chain = new_main_chain;
-                        // update main_chain_tx_hash
-                        self.main_chain_tx_hash = self
-                            .main_chain_tx_hash
-                            .iter()
-                            .cloned()
-                            .filter(|hash| !finalized_tx_hash_list.contains(hash))
-                            .collect();
-                        let new_fork_tree = self.fork_tree.split_off(finalized_blocks_number);
-                        self.fork_tree = new_fork_tree;
-                        self.fork_tree
-                            .resize(self.block_delay_number as usize * 2 + 2, HashMap::new());
-                    }
-                    // candidate_block need update
-                    self.clear_proposal();
-
-                    let config = self.get_system_config().await;
-
-                    return Ok((
-                        ConsensusConfiguration {
-                            height,
-                            block_interval: config.block_interval,
-                            validators: config.validators,
-                        },
-                        ChainStatus {
-                            version: config.version,
-                            chain_id: config.chain_id,
-                            height,
-                            hash: Some(Hash {
-                                hash: self.block_hash.clone(),
-                            }),
-                            address: None,
-                        },
+                        "candidate_chain interrupted".to_string(),
                     ));
                 }
-                break;
+            };
+            if prev_full_block.proof.is_empty() {
+                warn!("candidate_chain has no proof");
+                return Err(Error::ExpectError(
+                    "candidate_chain has no proof".to_string(),
+                ));
+            }
+            let prev_tx_hashes = full_to_compact(prev_full_block.to_owned())
+                .body
+                .unwrap()
+                .tx_hashes;
+            if prev_tx_hashes
+                .iter()
+                .any(|hash| candidate_chain_tx_hash.contains(hash))
+            {
+                // candidate_chain has dup tx, so failed
+                warn!("candidate_chain has dup tx");
+                return Err(Error::ExpectError("candidate_chain has dup tx".to_string()));
+            }
+            // checked now if the parent was unknown when the block was added
+            let prev_header = prev_full_block.header.as_ref().unwrap();
+            if timestamp <= prev_header.timestamp {
+                warn!("candidate_chain has timestamp not after parent");
+                return Err(Error::TimestampNotAfterParent(
+                    timestamp,
+                    prev_header.timestamp,
+                ));
             }
+            candidate_chain.push(prev_hash.clone());
+            candidate_chain_tx_hash.extend_from_slice(&prev_tx_hashes);
+            timestamp = prev_header.timestamp;
+            prev_hash = prev_header.prevhash.clone();
+        }
+
+        if prev_hash != self.block_hash {
+            warn!("candidate_chain can't fit finalized block");
+            // break this invalid chain
+            let blk_hash = candidate_chain.last().unwrap();
+            self.fork_tree.get_mut(0).unwrap().remove(blk_hash);
+            return Err(Error::ExpectError(
+                "candidate_chain can't fit finalized block".to_string(),
+            ));
+        }
+        let finalized = get_compact_block(self.block_number).await?.0;
+        let finalized_timestamp = finalized.header.ok_or(Error::NoneBlockHeader)?.timestamp;
+        if timestamp <= finalized_timestamp {
+            warn!("candidate_chain has timestamp not after finalized block");
+            return Err(Error::TimestampNotAfterParent(
+                timestamp,
+                finalized_timestamp,
+            ));
+        }
+
+        // only a block of a valid chain enters fork tree, a shorter one stays as a fork
+        self.fork_tree[index].insert(block_hash, full_block);
+
+        // if candidate_chain longer than original main_chain
+        if candidate_chain.len() <= self.main_chain.len() {
+            return Err(Error::NoForkTree);
         }
 
-        Err(Error::NoForkTree)
+        // replace the main_chain
+        candidate_chain.reverse();
+        self.main_chain = candidate_chain;
+        self.main_chain_tx_hash = candidate_chain_tx_hash;
+        print_main_chain(&self.main_chain, self.block_number);
+        // log the committed block with its proof before finalizing anything
+        self.save_main_chain();
+
+        // finalize blocks out of delay from the front of main_chain one by one,
+        // so a failure leaves the chain at the last finalized block
+        while self.main_chain.len() > self.block_delay_number as usize {
+            let block_hash = self.main_chain[0].clone();
+            let block = self.fork_tree[0]
+                .get(&block_hash)
+                .cloned()
+                .ok_or(Error::NoForkTree)?;
+            let tx_hashes = full_to_compact(block.clone()).body.unwrap().tx_hashes;
+            self.finalize_block(block, block_hash.clone()).await?;
+
+            self.block_number += 1;
+            self.block_hash = block_hash;
+            self.main_chain.remove(0);
+            self.main_chain_tx_hash
+                .retain(|hash| !tx_hashes.contains(hash));
+            // forks of the finalized height are dropped
+            self.fork_tree.remove(0);
+            self.fork_tree.push(HashMap::new());
+            self.save_main_chain();
+        }
+        // candidate_block need update
+        self.clear_proposal();
+
+        let config = self.get_system_config().await;
+
+        Ok((
+            ConsensusConfiguration {
+                height,
+                block_interval: config.block_interval,
+                validators: config.validators,
+            },
+            ChainStatus {
+                version: config.version,
+                chain_id: config.chain_id,
+                height,
+                hash: Some(Hash {
+                    hash: self.block_hash.clone(),
+                }),
+                address: None,
+            },
+        ))
     }
 
     pub async fn process_block(
@@ -613,6 +735,8 @@ impl Chain {
             return Err(Error::BlockCheckError);
         }
 
+        self.validate_header(&block).await?;
+
         let proposal_bytes = self.assemble_proposal(block.clone(), height).await?;
 
         check_block(height, proposal_bytes, block.proof.clone())
@@ -657,6 +781,83 @@ impl Chain {
         ))
     }
 
+    // check header of a block following the finalized block or a block in fork tree
+    // timestamp after an unknown parent is checked when the block is committed
+    async fn validate_header(&self, block: &Block) -> Result<(), Error> {
+        let header = block.header.as_ref().ok_or(Error::NoneBlockHeader)?;
+        let validators = self.validators_at(header).await?;
+        check_header(
+            block,
+            &validators,
+            self.merkle_activation_height,
+            unix_now(),
+        )?;
+        if let Some(parent_timestamp) = self.parent_timestamp(header).await? {
+            check_timestamp(header, parent_timestamp)?;
+        }
+        Ok(())
+    }
+
+    // None if parent is neither the finalized block nor in fork tree
+    async fn parent_timestamp(&self, header: &BlockHeader) -> Result<Option<u64>, Error> {
+        if header.prevhash == self.block_hash {
+            let parent = get_compact_block(self.block_number).await?.0;
+            return Ok(Some(parent.header.ok_or(Error::NoneBlockHeader)?.timestamp));
+        }
+        // index of parent in fork tree
+        Ok(header
+            .height
+            .checked_sub(self.block_number + 2)
+            .and_then(|index| self.fork_tree.get(index as usize))
+            .and_then(|blocks| blocks.get(&header.prevhash))
+            .and_then(|parent| parent.header.as_ref())
+            .map(|parent| parent.timestamp))
+    }
+
+    // unfinalized ancestors of a block from the lowest, None if some of them not in fork tree
+    fn unfinalized_ancestors(&self, header: &BlockHeader) -> Option<Vec<&Block>> {
+        let mut ancestors = Vec::new();
+        let mut prevhash = &header.prevhash;
+        let mut index = header.height.checked_sub(self.block_number + 1)?;
+        while prevhash != &self.block_hash {
+            index = index.checked_sub(1)?;
+            let parent = self.fork_tree.get(index as usize)?.get(prevhash)?;
+            prevhash = &parent.header.as_ref()?.prevhash;
+            ancestors.push(parent);
+        }
+        ancestors.reverse();
+        Some(ancestors)
+    }
+
+    // validators in force at the block, i.e. after its parent is finalized.
+    // only scheduled changes are applied if some unfinalized ancestor is unknown.
+    async fn validators_at(&self, header: &BlockHeader) -> Result<Vec<Vec<u8>>, Error> {
+        let parent_height = header.height.saturating_sub(1);
+        if parent_height < self.block_number {
+            return self
+                .validator_history
+                .range(..=parent_height)
+                .next_back()
+                .map(|(_, validators)| validators.clone())
+                .ok_or_else(|| {
+                    Error::ExpectError(format!("no validators of height {}", parent_height))
+                });
+        }
+
+        let mut sys_config = self.get_system_config().await;
+        match self.unfinalized_ancestors(header) {
+            Some(ancestors) => {
+                for ancestor in ancestors {
+                    apply_block_changes(&mut sys_config, ancestor);
+                }
+            }
+            None => {
+                sys_config.activate(parent_height);
+            }
+        }
+        Ok(sys_config.validators)
+    }
+
     pub async fn get_system_config(&self) -> SystemConfig {
         let rd = self.auth.read().await;
         rd.get_system_config()
@@ -675,22 +876,43 @@ impl Chain {
         }
     }
 
+    // height of the unfinalized main_chain block which contains tx
+    pub fn get_pending_tx_height(&self, tx_hash: &[u8]) -> Option<u64> {
+        if !self.main_chain_tx_hash.iter().any(|hash| hash == tx_hash) {
+            return None;
+        }
+        for (index, block_hash) in self.main_chain.iter().enumerate() {
+            let block = self.fork_tree[index].get(block_hash)?;
+            if let Some(body) = block.body.as_ref() {
+                if body
+                    .body
+                    .iter()
+                    .any(|raw_tx| get_tx_hash(raw_tx).ok().as_deref() == Some(tx_hash))
+                {
+                    return Some(self.block_number + index as u64 + 1);
+                }
+            }
+        }
+        None
+    }
+
     pub fn check_dup_tx(&self, tx_hash: &Vec<u8>) -> bool {
         self.main_chain_tx_hash.contains(tx_hash)
     }
 
     // todo use &RawTransactions
     pub async fn check_transactions(&self, raw_txs: RawTransactions) -> Result<(), Error> {
-        let auth = self.auth.read().await;
+        {
+            let auth = self.auth.read().await;
+            for raw_tx in raw_txs.body.iter() {
+                auth.precheck_raw_tx(raw_tx).map_err(Error::TxRejected)?;
+            }
+        }
 
-        // todo not do clone
-        for raw_tx in raw_txs.body.clone() {
-            let tx_hash = auth
-                .check_raw_tx(raw_tx)
-                .await
-                .map_err(|e| Error::ExpectError(e))?;
+        let tx_hashes = batch_verify_raw_txs(raw_txs.body).await?;
 
-            if self.check_dup_tx(&tx_hash.clone()) {
+        for tx_hash in tx_hashes {
+            if self.check_dup_tx(&tx_hash) {
                 return Err(Error::DupTransaction(tx_hash));
             }
         }
@@ -713,3 +935,1277 @@ impl Chain {
         self.candidate_block = None;
     }
 }
+
+// header checks independent of the parent, timestamps in ms
+fn check_header(
+    block: &Block,
+    validators: &[Vec<u8>],
+    merkle_activation_height: u64,
+    now: u64,
+) -> Result<(), Error> {
+    check_block_version(block, merkle_activation_height)?;
+    let header = block.header.as_ref().ok_or(Error::NoneBlockHeader)?;
+    let tx_hashes = get_tx_hash_list(block.body.as_ref().ok_or(Error::NoneBlockBody)?)?;
+    if header.transactions_root != transactions_root(block.version, &tx_hashes) {
+        return Err(Error::TransactionsRootMismatch(header.height));
+    }
+    if !validators.contains(&header.proposer) {
+        return Err(Error::ProposerNotValidator(header.proposer.clone()));
+    }
+    if header.timestamp > now + MAX_TIMESTAMP_DRIFT {
+        return Err(Error::TimestampInFuture(header.timestamp, now));
+    }
+    Ok(())
+}
+
+// stored blocks before merkle_activation_height keep their legacy root
+fn check_block_version(block: &Block, merkle_activation_height: u64) -> Result<(), Error> {
+    let header = block.header.as_ref().ok_or(Error::NoneBlockHeader)?;
+    if header.height >= merkle_activation_height && block.version < BLOCK_VERSION_MERKLE {
+        return Err(Error::LegacyBlockVersion(header.height, block.version));
+    }
+    Ok(())
+}
+
+fn check_timestamp(header: &BlockHeader, parent_timestamp: u64) -> Result<(), Error> {
+    if header.timestamp <= parent_timestamp {
+        return Err(Error::TimestampNotAfterParent(
+            header.timestamp,
+            parent_timestamp,
+        ));
+    }
+    Ok(())
+}
+
+// sys_config changes of a block, in force after it is finalized
+fn apply_block_changes(sys_config: &mut SystemConfig, block: &Block) {
+    for raw_tx in block.body.iter().flat_map(|body| body.body.iter()) {
+        if let Some(Tx::UtxoTx(utxo_tx)) = &raw_tx.tx {
+            sys_config.update(utxo_tx, false);
+        }
+    }
+    if let Some(header) = block.header.as_ref() {
+        sys_config.activate(header.height);
+    }
+}
+
+// region 0: lock_id - hash of the last utxo tx of it
+fn utxo_tx_hash_write(lock_id: u64, tx_hash: Vec<u8>) -> StorageWrite {
+    StorageWrite {
+        key: lock_id.to_be_bytes().to_vec(),
+        value: tx_hash,
+    }
+}
+
+// region 0: lock_id | LOCK_ID_HISTORY_FLAG - changes of lock_id applied
+// history is read from earlier writes of the same block before storage
+async fn utxo_history_write(
+    lock_id: u64,
+    height: u64,
+    tx_hash: &[u8],
+    writes: &[StorageWrite],
+) -> Result<Option<StorageWrite>, Error> {
+    let key = (lock_id | LOCK_ID_HISTORY_FLAG).to_be_bytes().to_vec();
+    let mut history = match writes.iter().rev().find(|write| write.key == key) {
+        Some(write) => write.value.clone(),
+        None => load_data_maybe_empty(0, key.clone())
+            .await
+            .map_err(Error::InternalError)?,
+    };
+    let record = encode_history_record(height, tx_hash);
+    // block finalized again after restart
+    if history.ends_with(&record) {
+        return Ok(None);
+    }
+    history.extend_from_slice(&record);
+    Ok(Some(StorageWrite {
+        key,
+        value: history,
+    }))
+}
+
+// region 0: 2 - block being finalized, empty if none
+fn finalizing_key() -> Vec<u8> {
+    2u64.to_be_bytes().to_vec()
+}
+
+// region 0: 3 - height of the last block sent to executor
+fn executing_key() -> Vec<u8> {
+    3u64.to_be_bytes().to_vec()
+}
+
+async fn store_finalizing(finalizing: &FinalizingBlock) -> Result<(), Error> {
+    let mut buf = Vec::with_capacity(finalizing.encoded_len());
+    finalizing
+        .encode(&mut buf)
+        .map_err(|_| Error::EncodeError("encode FinalizingBlock failed".to_owned()))?;
+    store_data(0, finalizing_key(), buf).await.map_err(|e| {
+        warn!("store finalizing block failed, error: {}", e);
+        Error::StoreError
+    })?;
+    Ok(())
+}
+
+// Store everything of a finalized block, current height and hash last, then clear the journal.
+// Replayed on restart, so every write is idempotent.
+async fn apply_finalizing(block: Block, finalizing: &FinalizingBlock) -> Result<(), Error> {
+    let block_height = block.header.as_ref().ok_or(Error::NoneBlockHeader)?.height;
+    let block_height_bytes = block_height.to_be_bytes().to_vec();
+    let block_hash = &finalizing.block_hash;
+
+    for write in finalizing.writes.iter() {
+        store_data(0, write.key.clone(), write.value.clone())
+            .await
+            .map_err(|e| {
+                warn!(
+                    "store global(0x{}) failed, error: {}",
+                    hex::encode(&write.key),
+                    e
+                );
+                Error::StoreError
+            })?;
+    }
+
+    store_data(11, block_height_bytes.clone(), finalizing.block.clone())
+        .await
+        .map_err(|e| {
+            warn!("store Block({}) failed, error: {}", block_height, e);
+            Error::StoreError
+        })?;
+
+    // a block whose state root is stored has been executed
+    let state_root = load_data_maybe_empty(6, block_height_bytes.clone())
+        .await
+        .map_err(Error::InternalError)?;
+    if state_root.is_empty() {
+        // sent to executor before but the result is lost, the executor may have executed it
+        let resend = load_data_maybe_empty(0, executing_key())
+            .await
+            .map_err(Error::InternalError)?
+            == block_height_bytes;
+        if resend {
+            warn!(
+                "block({}) was sent to executor before, resend it for the state root",
+                block_height
+            );
+        } else {
+            store_data(0, executing_key(), block_height_bytes.clone())
+                .await
+                .map_err(|e| {
+                    warn!(
+                        "store executing height({}) failed, error: {}",
+                        block_height, e
+                    );
+                    Error::StoreError
+                })?;
+        }
+        // exec bloc
+        let executed_block_hash = exec_block(block).await.map_err(|e| {
+            warn!("exec_block({}) error: {}", block_height, e);
+            if resend {
+                Error::ReexecuteError(block_height)
+            } else {
+                Error::ExecuteError
+            }
+        })?;
+        // region 6 : block_height - executed_block_hash
+        store_data(6, block_height_bytes.clone(), executed_block_hash.clone())
+            .await
+            .map_err(|e| {
+                warn!(
+                    "store state_root(0x{}) failed, error: {}",
+                    hex::encode(&executed_block_hash),
+                    e
+                );
+                Error::StoreError
+            })?;
+    }
+
+    // region 0: 0 - current height; 1 - current hash
+    store_data(0, 0u64.to_be_bytes().to_vec(), block_height_bytes)
+        .await
+        .map_err(|e| {
+            warn!(
+                "store current height({}) failed, error: {}",
+                block_height, e
+            );
+            Error::StoreError
+        })?;
+    store_data(0, 1u64.to_be_bytes().to_vec(), block_hash.clone())
+        .await
+        .map_err(|e| {
+            warn!(
+                "store current block_hash(0x{}) failed, error: {}",
+                hex::encode(block_hash),
+                e
+            );
+            Error::StoreError
+        })?;
+
+    store_data(0, finalizing_key(), vec![]).await.map_err(|e| {
+        warn!("clear finalizing block failed, error: {}", e);
+        Error::StoreError
+    })?;
+    Ok(())
+}
+
+/// Complete the block finalization interrupted by last exit, must run before current height is
+/// read. A journal not following current block is dropped, nothing of it has been stored.
+pub async fn recover_finalization() -> Result<(), Error> {
+    let bytes = load_data_maybe_empty(0, finalizing_key())
+        .await
+        .map_err(Error::InternalError)?;
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    let finalizing = FinalizingBlock::decode(bytes.as_slice())
+        .map_err(|_| Error::DecodeError("decode FinalizingBlock failed".to_owned()))?;
+    let block = Block::decode(finalizing.block.as_slice())
+        .map_err(|_| Error::DecodeError("decode Block failed".to_owned()))?;
+    let header = block.header.as_ref().ok_or(Error::NoneBlockHeader)?;
+    let height = header.height;
+
+    let current_height_bytes = load_data_maybe_empty(0, 0u64.to_be_bytes().to_vec())
+        .await
+        .map_err(Error::InternalError)?;
+    let current_hash = load_data_maybe_empty(0, 1u64.to_be_bytes().to_vec())
+        .await
+        .map_err(Error::InternalError)?;
+    let follows = if current_height_bytes.is_empty() {
+        // genesis block
+        height == 0
+    } else {
+        let mut bytes: [u8; 8] = [0; 8];
+        bytes[..8].clone_from_slice(&current_height_bytes[..8]);
+        let current_height = u64::from_be_bytes(bytes);
+        // stopped after the current height was stored
+        height == current_height
+            || (height == current_height + 1 && header.prevhash == current_hash)
+    };
+
+    if follows {
+        info!(
+            "recover_finalization: complete block({}) 0x{}",
+            height,
+            hex::encode(&finalizing.block_hash)
+        );
+        apply_finalizing(block, &finalizing).await
+    } else {
+        warn!(
+            "recover_finalization: drop block({}) 0x{} not following current block",
+            height,
+            hex::encode(&finalizing.block_hash)
+        );
+        store_data(0, finalizing_key(), vec![]).await.map_err(|e| {
+            warn!("clear finalizing block failed, error: {}", e);
+            Error::StoreError
+        })?;
+        Ok(())
+    }
+}
+
+/// Write history of sys_config changes committed before history was kept, so historical
+/// sys_config covers them. Changes of a lock_id are found from its last one by pre_tx_hash,
+/// lock_ids with history already are skipped.
+pub async fn backfill_utxo_history() -> Result<(), Error> {
+    for lock_id in LOCK_ID_VERSION..LOCK_ID_BUTTON {
+        let key = (lock_id | LOCK_ID_HISTORY_FLAG).to_be_bytes().to_vec();
+        let history = load_data_maybe_empty(0, key.clone())
+            .await
+            .map_err(Error::InternalError)?;
+        if !history.is_empty() {
+            continue;
+        }
+        let mut tx_hash = load_data_maybe_empty(0, lock_id.to_be_bytes().to_vec())
+            .await
+            .map_err(Error::InternalError)?;
+
+        // from the last change back to the first
+        let mut records = Vec::new();
+        while !tx_hash.is_empty() && tx_hash.iter().any(|b| *b != 0) {
+            let utxo_tx = match db_get_tx(&tx_hash).await?.tx {
+                Some(Tx::UtxoTx(utxo_tx)) => utxo_tx.transaction,
+                _ => None,
+            }
+            .ok_or_else(|| Error::ExpectError("tx is not utxo_tx".to_owned()))?;
+            // a scheduled change is applied at its activation height
+            let height = if utxo_tx.lock_id & LOCK_ID_SCHEDULED_FLAG != 0 {
+                decode_scheduled(&utxo_tx.output)
+                    .ok_or_else(|| Error::ExpectError("invalid scheduled change".to_owned()))?
+                    .0
+            } else {
+                load_tx_info(&tx_hash).await?.0
+            };
+            records.push(encode_history_record(height, &tx_hash));
+            tx_hash = utxo_tx.pre_tx_hash;
+        }
+        if records.is_empty() {
+            continue;
+        }
+
+        info!(
+            "backfill history of lock_id {}: {} changes",
+            lock_id,
+            records.len()
+        );
+        records.reverse();
+        store_data(0, key, records.concat()).await.map_err(|e| {
+            warn!("store history of lock_id {} failed, error: {}", lock_id, e);
+            Error::StoreError
+        })?;
+    }
+    Ok(())
+}
+
+// sys_config after the finalized block, rebuilt from init sys_config and utxo txs in region 0,
+// applied changes first, then changes scheduled on them
+pub async fn restore_system_config(init_sys_config: SystemConfig) -> Result<SystemConfig, Error> {
+    let mut sys_config = init_sys_config;
+    for flag in [0, LOCK_ID_SCHEDULED_FLAG] {
+        for lock_id in LOCK_ID_VERSION..LOCK_ID_BUTTON {
+            let tx_hash = load_data_maybe_empty(0, (lock_id | flag).to_be_bytes().to_vec())
+                .await
+                .map_err(Error::InternalError)?;
+            if tx_hash.is_empty() {
+                continue;
+            }
+            match db_get_tx(&tx_hash).await?.tx {
+                Some(Tx::UtxoTx(utxo_tx)) => {
+                    sys_config.update(&utxo_tx, flag == 0);
+                }
+                _ => return Err(Error::ExpectError("tx is not utxo_tx".to_owned())),
+            }
+        }
+    }
+    Ok(sys_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        backfill_utxo_history, check_header, check_timestamp, recover_finalization,
+        restore_system_config, store_finalizing, utxo_history_write, utxo_tx_hash_write, Chain,
+        MAX_TIMESTAMP_DRIFT,
+    };
+    use crate::auth::Authentication;
+    use crate::error::Error;
+    use crate::genesis::GenesisBlock;
+    use crate::journal::Journal;
+    use crate::merkle::{transactions_root, BLOCK_VERSION_MERKLE};
+    use crate::pool::{FifoPolicy, PackageLimit, Pool, PoolLimit};
+    use crate::protocol::controller_storage::{FinalizingBlock, StorageWrite};
+    use crate::util::{
+        full_to_compact, get_block_hash, get_tx_hash_list, init_grpc_client, init_test_crypto,
+        STORAGE_CLIENT,
+    };
+    use crate::utxo_set::{
+        encode_history_record, SystemConfig, LOCK_ID_ADMIN, LOCK_ID_BLOCK_INTERVAL,
+        LOCK_ID_HISTORY_FLAG, LOCK_ID_SCHEDULED_FLAG, LOCK_ID_VALIDATORS, LOCK_ID_VERSION,
+    };
+    use cita_cloud_proto::blockchain::raw_transaction::Tx;
+    use cita_cloud_proto::blockchain::{
+        Block, BlockHeader, RawTransaction, RawTransactions, UnverifiedTransaction,
+        UnverifiedUtxoTransaction, UtxoTransaction,
+    };
+    use cita_cloud_proto::common::{
+        proposal_enum::Proposal, BftProposal, Hash, ProposalEnum, SimpleResponse,
+    };
+    use cita_cloud_proto::executor::{
+        executor_service_server::{ExecutorService, ExecutorServiceServer},
+        CallRequest, CallResponse,
+    };
+    use cita_cloud_proto::storage::{
+        storage_service_server::{StorageService, StorageServiceServer},
+        Content, ExtKey, Value,
+    };
+    use prost::Message;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::{OnceCell, RwLock};
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+    use tonic::{Request, Response, Status};
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("controller-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn new_chain(
+        block_delay_number: u32,
+        block_number: u64,
+        block_hash: Vec<u8>,
+        journal: Option<Journal>,
+    ) -> Chain {
+        let pool = Pool::new(
+            PackageLimit::default(),
+            Box::new(FifoPolicy),
+            PoolLimit::default(),
+        );
+        let sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        Chain::new(
+            block_delay_number,
+            0,
+            block_number,
+            block_hash,
+            Arc::new(RwLock::new(pool)),
+            Arc::new(RwLock::new(Authentication::new(sys_config))),
+            GenesisBlock {
+                timestamp: 0,
+                prevhash: String::new(),
+                commit_sys_config: false,
+                sys_config_hash: vec![],
+            },
+            vec![],
+            0,
+            vec![1; 20],
+            journal,
+        )
+    }
+
+    fn block(height: u64, prevhash: Vec<u8>, proof: Vec<u8>) -> (Vec<u8>, Block) {
+        let header = BlockHeader {
+            prevhash,
+            height,
+            ..Default::default()
+        };
+        let block_hash = get_block_hash(Some(&header)).unwrap();
+        let block = Block {
+            version: BLOCK_VERSION_MERKLE,
+            header: Some(header),
+            body: Some(RawTransactions { body: vec![] }),
+            proof,
+        };
+        (block_hash, block)
+    }
+
+    #[test]
+    fn restore_main_chain_test() {
+        init_test_crypto();
+        let path = journal_path("chain_journal");
+        let h5 = vec![5; 32];
+        let (_, b5) = block(5, vec![4; 32], vec![1]);
+        let (h6, b6) = block(6, h5.clone(), vec![1]);
+        let (h7, b7) = block(7, h6.clone(), vec![1]);
+        // no proof
+        let (_, b8) = block(8, h7.clone(), vec![]);
+        let (_, b9) = block(9, h7.clone(), vec![1]);
+        let mut journal = Journal::open(&path).unwrap();
+        journal.rewrite(&[b5, b6, b7.clone(), b8, b9]).unwrap();
+
+        // finalized block is skipped, stops at block without proof
+        let mut chain = new_chain(3, 5, h5.clone(), Some(journal));
+        chain.restore_main_chain();
+        assert_eq!(chain.main_chain, vec![h6.clone(), h7.clone()]);
+        assert_eq!(chain.get_block_number(true), 7);
+
+        // journal only keeps the restored blocks
+        let mut chain = new_chain(3, 5, h5.clone(), Some(Journal::open(&path).unwrap()));
+        chain.restore_main_chain();
+        assert_eq!(chain.main_chain, vec![h6.clone(), h7.clone()]);
+        assert_eq!(
+            Journal::open(&path).unwrap().load::<Block>().unwrap().len(),
+            2
+        );
+
+        // block 6 finalized meanwhile
+        let mut chain = new_chain(3, 6, h6, Some(Journal::open(&path).unwrap()));
+        chain.restore_main_chain();
+        assert_eq!(chain.main_chain, vec![h7]);
+
+        // another fork was finalized
+        let mut chain = new_chain(3, 6, vec![6; 32], Some(Journal::open(&path).unwrap()));
+        chain.restore_main_chain();
+        assert!(chain.main_chain.is_empty());
+        assert!(Journal::open(&path)
+            .unwrap()
+            .load::<Block>()
+            .unwrap()
+            .is_empty());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn utxo_history_write_test() {
+        // history written earlier in the same block is appended to, storage is not read
+        let key = (LOCK_ID_VERSION | LOCK_ID_HISTORY_FLAG)
+            .to_be_bytes()
+            .to_vec();
+        let first = encode_history_record(5, &[1; 32]);
+        let writes = vec![
+            utxo_tx_hash_write(LOCK_ID_VERSION, vec![1; 32]),
+            StorageWrite {
+                key: key.clone(),
+                value: first.clone(),
+            },
+        ];
+        let write = utxo_history_write(LOCK_ID_VERSION, 6, &[2; 32], &writes)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(write.key, key);
+        assert_eq!(
+            write.value,
+            [first, encode_history_record(6, &[2; 32])].concat()
+        );
+
+        // same record again
+        assert!(utxo_history_write(LOCK_ID_VERSION, 5, &[1; 32], &writes)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn check_header_test() {
+        init_test_crypto();
+        let body: Vec<RawTransaction> = (1..=3)
+            .map(|i| RawTransaction {
+                tx: Some(Tx::NormalTx(UnverifiedTransaction {
+                    transaction_hash: vec![i; 32],
+                    ..Default::default()
+                })),
+            })
+            .collect();
+        let tx_hashes: Vec<Vec<u8>> = (1..=3).map(|i| vec![i; 32]).collect();
+        let block = |version: u32, header: BlockHeader| Block {
+            version,
+            header: Some(header),
+            body: Some(RawTransactions { body: body.clone() }),
+            proof: vec![],
+        };
+        let header = BlockHeader {
+            height: 2,
+            timestamp: 100_000,
+            transactions_root: transactions_root(BLOCK_VERSION_MERKLE, &tx_hashes),
+            proposer: vec![1; 20],
+            ..Default::default()
+        };
+        let validators = vec![vec![2; 20], vec![1; 20]];
+        let check = |block: &Block, parent_timestamp: u64, now: u64| {
+            check_header(block, &validators, 3, now)
+                .and_then(|_| check_timestamp(block.header.as_ref().unwrap(), parent_timestamp))
+        };
+
+        let merkle_block = block(BLOCK_VERSION_MERKLE, header.clone());
+        assert!(check(&merkle_block, 99_999, 100_000).is_ok());
+        // clock of proposer a bit ahead
+        assert!(check(&merkle_block, 99_999, 100_000 - MAX_TIMESTAMP_DRIFT).is_ok());
+        assert!(matches!(
+            check(&merkle_block, 99_999, 99_999 - MAX_TIMESTAMP_DRIFT),
+            Err(Error::TimestampInFuture(100_000, _))
+        ));
+        assert!(matches!(
+            check(&merkle_block, 100_000, 100_000),
+            Err(Error::TimestampNotAfterParent(100_000, 100_000))
+        ));
+
+        // root of another version
+        assert!(matches!(
+            check(&block(0, header.clone()), 99_999, 100_000),
+            Err(Error::TransactionsRootMismatch(2))
+        ));
+        let legacy_header = BlockHeader {
+            transactions_root: transactions_root(0, &tx_hashes),
+            ..header.clone()
+        };
+        assert!(check(&block(0, legacy_header.clone()), 99_999, 100_000).is_ok());
+        // legacy root only before merkle_activation_height
+        let legacy_header = BlockHeader {
+            height: 3,
+            ..legacy_header
+        };
+        assert!(matches!(
+            check(&block(0, legacy_header), 99_999, 100_000),
+            Err(Error::LegacyBlockVersion(3, 0))
+        ));
+
+        let mut other_body = merkle_block.clone();
+        other_body.body.as_mut().unwrap().body.pop();
+        assert!(matches!(
+            check(&other_body, 99_999, 100_000),
+            Err(Error::TransactionsRootMismatch(2))
+        ));
+
+        let other_proposer = BlockHeader {
+            proposer: vec![3; 20],
+            ..header
+        };
+        assert!(matches!(
+            check(
+                &block(BLOCK_VERSION_MERKLE, other_proposer),
+                99_999,
+                100_000
+            ),
+            Err(Error::ProposerNotValidator(_))
+        ));
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        // region, key - value
+        storage: Mutex<HashMap<(u32, Vec<u8>), Vec<u8>>>,
+        executed: Mutex<Vec<Block>>,
+        // executor rejects blocks if set
+        exec_down: Mutex<bool>,
+        // tests share the services, one at a time
+        lock: tokio::sync::Mutex<()>,
+    }
+
+    impl MockState {
+        fn reset(&self) {
+            self.storage.lock().unwrap().clear();
+            self.executed.lock().unwrap().clear();
+            *self.exec_down.lock().unwrap() = false;
+        }
+
+        fn get(&self, region: u32, key: u64) -> Option<Vec<u8>> {
+            let key = (region, key.to_be_bytes().to_vec());
+            self.storage.lock().unwrap().get(&key).cloned()
+        }
+
+        fn set(&self, region: u32, key: u64, value: Vec<u8>) {
+            self.set_raw(region, key.to_be_bytes().to_vec(), value);
+        }
+
+        fn set_raw(&self, region: u32, key: Vec<u8>, value: Vec<u8>) {
+            self.storage.lock().unwrap().insert((region, key), value);
+        }
+
+        // compact block and proof of block, as loaded by get_compact_block
+        fn set_block(&self, key: Vec<u8>, block: &Block) {
+            let mut buf = Vec::new();
+            full_to_compact(block.clone()).encode(&mut buf).unwrap();
+            self.set_raw(10, key.clone(), buf);
+            self.set_raw(5, key, block.proof.clone());
+        }
+
+        fn executed_hashes(&self) -> Vec<Vec<u8>> {
+            self.executed
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|block| get_block_hash(block.header.as_ref()).unwrap())
+                .collect()
+        }
+    }
+
+    struct MockStorage(Arc<MockState>);
+
+    #[tonic::async_trait]
+    impl StorageService for MockStorage {
+        async fn store(
+            &self,
+            request: Request<Content>,
+        ) -> Result<Response<SimpleResponse>, Status> {
+            let content = request.into_inner();
+            // compact block and proof are kept with full block as storage service does
+            if content.region == 11 {
+                if let Ok(block) = Block::decode(content.value.as_slice()) {
+                    self.0.set_block(content.key.clone(), &block);
+                }
+            }
+            self.0
+                .storage
+                .lock()
+                .unwrap()
+                .insert((content.region, content.key), content.value);
+            Ok(Response::new(SimpleResponse { is_success: true }))
+        }
+
+        async fn load(&self, request: Request<ExtKey>) -> Result<Response<Value>, Status> {
+            let key = request.into_inner();
+            match self.0.storage.lock().unwrap().get(&(key.region, key.key)) {
+                Some(value) => Ok(Response::new(Value {
+                    value: value.clone(),
+                })),
+                None => Err(Status::not_found("key not found")),
+            }
+        }
+
+        async fn delete(
+            &self,
+            request: Request<ExtKey>,
+        ) -> Result<Response<SimpleResponse>, Status> {
+            let key = request.into_inner();
+            self.0
+                .storage
+                .lock()
+                .unwrap()
+                .remove(&(key.region, key.key));
+            Ok(Response::new(SimpleResponse { is_success: true }))
+        }
+    }
+
+    struct MockExecutor(Arc<MockState>);
+
+    #[tonic::async_trait]
+    impl ExecutorService for MockExecutor {
+        async fn exec(&self, request: Request<Block>) -> Result<Response<Hash>, Status> {
+            if *self.0.exec_down.lock().unwrap() {
+                return Err(Status::unavailable("exec"));
+            }
+            let block = request.into_inner();
+            let height = block.header.as_ref().unwrap().height;
+            self.0.executed.lock().unwrap().push(block);
+            Ok(Response::new(Hash {
+                hash: vec![height as u8; 32],
+            }))
+        }
+
+        async fn call(
+            &self,
+            _request: Request<CallRequest>,
+        ) -> Result<Response<CallResponse>, Status> {
+            Err(Status::unimplemented("call"))
+        }
+    }
+
+    static MOCK: OnceCell<Arc<MockState>> = OnceCell::const_new();
+
+    // storage and executor behind the grpc clients of util, served with the clients in a
+    // runtime of their own as runtimes of tests come and go
+    async fn mock_services() -> Arc<MockState> {
+        MOCK.get_or_init(|| async {
+            let state = Arc::new(MockState::default());
+            // served on the listener bound here, so the port can't be taken meanwhile
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let server = Server::builder()
+                .add_service(StorageServiceServer::new(MockStorage(state.clone())))
+                .add_service(ExecutorServiceServer::new(MockExecutor(state.clone())));
+            std::thread::spawn(move || {
+                let rt = tokio::runtime::Runtime::new().unwrap();
+                rt.block_on(async move {
+                    init_grpc_client(port, port, port, port);
+                    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                    server
+                        .serve_with_incoming(TcpListenerStream::new(listener))
+                        .await
+                        .unwrap();
+                })
+            });
+            while STORAGE_CLIENT.get().is_none() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            state
+        })
+        .await
+        .clone()
+    }
+
+    fn tx_block(height: u64, prevhash: Vec<u8>, tx: u8) -> (Vec<u8>, Block) {
+        let (_, mut block) = block(height, prevhash, vec![]);
+        block.body = Some(RawTransactions {
+            body: vec![RawTransaction {
+                tx: Some(Tx::NormalTx(UnverifiedTransaction {
+                    transaction_hash: vec![tx; 32],
+                    ..Default::default()
+                })),
+            }],
+        });
+        // keep headers distinct for blocks of the same height
+        block.header.as_mut().unwrap().timestamp = tx as u64;
+        (get_block_hash(block.header.as_ref()).unwrap(), block)
+    }
+
+    fn proposal(block: &Block) -> Vec<u8> {
+        let proposal = ProposalEnum {
+            proposal: Some(Proposal::BftProposal(BftProposal {
+                proposal: Some(block.clone()),
+                pre_state_root: vec![],
+                pre_proof: vec![],
+            })),
+        };
+        let mut buf = Vec::new();
+        proposal.encode(&mut buf).unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn delayed_commit_test() {
+        init_test_crypto();
+        let state = mock_services().await;
+        let _guard = state.lock.lock().await;
+        state.reset();
+
+        state.set_block(0u64.to_be_bytes().to_vec(), &block(0, vec![], vec![]).1);
+        let mut chain = new_chain(2, 0, vec![0; 32], None);
+        let mut hashes = Vec::new();
+        let mut prevhash = vec![0; 32];
+        for height in 1..=5u64 {
+            let (block_hash, block) = tx_block(height, prevhash, height as u8);
+            chain
+                .commit_block(height, &proposal(&block), &[height as u8; 4])
+                .await
+                .unwrap();
+            hashes.push(block_hash.clone());
+            prevhash = block_hash;
+        }
+
+        // the last block_delay_number blocks stay unfinalized
+        assert_eq!(chain.get_block_number(false), 3);
+        assert_eq!(chain.get_block_number(true), 5);
+        assert_eq!(chain.main_chain, hashes[3..].to_vec());
+        let mut tx_hashes = chain.main_chain_tx_hash.clone();
+        tx_hashes.sort();
+        assert_eq!(tx_hashes, vec![vec![4; 32], vec![5; 32]]);
+
+        // each finalized block is stored and executed with its own content and proof
+        assert_eq!(state.executed_hashes(), hashes[..3].to_vec());
+        let executed = state.executed.lock().unwrap().clone();
+        for (i, block) in executed.iter().enumerate() {
+            let height = i as u64 + 1;
+            assert_eq!(block.proof, vec![height as u8; 4]);
+            assert_eq!(
+                block.body.as_ref().unwrap().body[0],
+                tx_block(height, vec![], height as u8).1.body.unwrap().body[0]
+            );
+            let stored = state.get(11, height).unwrap();
+            assert_eq!(&Block::decode(stored.as_slice()).unwrap(), block);
+            assert_eq!(state.get(6, height).unwrap(), vec![height as u8; 32]);
+        }
+        assert_eq!(state.get(0, 0).unwrap(), 3u64.to_be_bytes().to_vec());
+        assert_eq!(state.get(0, 1).unwrap(), hashes[2]);
+        assert_eq!(state.get(0, 2).unwrap(), Vec::<u8>::new());
+    }
+
+    #[tokio::test]
+    async fn fork_commit_test() {
+        init_test_crypto();
+        let state = mock_services().await;
+        let _guard = state.lock.lock().await;
+        state.reset();
+
+        state.set_block(0u64.to_be_bytes().to_vec(), &block(0, vec![], vec![]).1);
+        let genesis_hash = vec![0; 32];
+        let mut chain = new_chain(1, 0, genesis_hash.clone(), None);
+        let (a1, block_a1) = tx_block(1, genesis_hash.clone(), 0xa1);
+        let (b1, block_b1) = tx_block(1, genesis_hash, 0xb1);
+        let (b2, block_b2) = tx_block(2, b1.clone(), 0xb2);
+        let (b3, block_b3) = tx_block(3, b2.clone(), 0xb3);
+
+        chain
+            .commit_block(1, &proposal(&block_a1), &[1])
+            .await
+            .unwrap();
+        assert_eq!(chain.main_chain, vec![a1]);
+
+        // not longer than main_chain, kept in fork tree with its proof
+        assert!(matches!(
+            chain.commit_block(1, &proposal(&block_b1), &[2]).await,
+            Err(Error::NoForkTree)
+        ));
+
+        // b2 switches main_chain to fork b, finalizing b1 rather than the block committed first
+        chain
+            .commit_block(2, &proposal(&block_b2), &[3])
+            .await
+            .unwrap();
+        assert_eq!(chain.get_block_number(false), 1);
+        assert_eq!(chain.main_chain, vec![b2.clone()]);
+        assert_eq!(state.executed_hashes(), vec![b1.clone()]);
+        assert_eq!(state.executed.lock().unwrap()[0].proof, vec![2]);
+
+        chain
+            .commit_block(3, &proposal(&block_b3), &[4])
+            .await
+            .unwrap();
+        assert_eq!(state.executed_hashes(), vec![b1, b2.clone()]);
+        assert_eq!(state.executed.lock().unwrap()[1].proof, vec![3]);
+        assert_eq!(state.get(0, 1).unwrap(), b2);
+
+        // parent not in fork tree
+        let (orphan_hash, orphan) = tx_block(4, vec![4; 32], 0xc4);
+        assert!(chain
+            .commit_block(4, &proposal(&orphan), &[5])
+            .await
+            .is_err());
+        assert_eq!(chain.get_block_number(true), 3);
+        // a block of a rejected chain doesn't enter fork tree
+        assert!(chain
+            .fork_tree
+            .iter()
+            .all(|blocks| !blocks.contains_key(&orphan_hash)));
+
+        // nor does a block with a tx of its chain
+        let (dup_hash, dup) = tx_block(4, b3.clone(), 0xb3);
+        assert!(chain.commit_block(4, &proposal(&dup), &[5]).await.is_err());
+        assert!(!chain.fork_tree[1].contains_key(&dup_hash));
+
+        // nor does a block without merkle root after merkle_activation_height
+        let (legacy_hash, mut legacy) = tx_block(4, b3, 0xc5);
+        legacy.version = 0;
+        assert!(matches!(
+            chain.commit_block(4, &proposal(&legacy), &[5]).await,
+            Err(Error::LegacyBlockVersion(4, 0))
+        ));
+        assert!(!chain.fork_tree[1].contains_key(&legacy_hash));
+    }
+
+    // block with header matching its body
+    fn proposed_block(
+        height: u64,
+        prevhash: Vec<u8>,
+        timestamp: u64,
+        proposer: u8,
+        body: Vec<RawTransaction>,
+    ) -> (Vec<u8>, Block) {
+        let (_, mut block) = block(height, prevhash, vec![]);
+        let tx_hashes = get_tx_hash_list(&RawTransactions { body: body.clone() }).unwrap();
+        let header = block.header.as_mut().unwrap();
+        header.timestamp = timestamp;
+        header.proposer = vec![proposer; 20];
+        header.transactions_root = transactions_root(BLOCK_VERSION_MERKLE, &tx_hashes);
+        block.body = Some(RawTransactions { body });
+        (get_block_hash(block.header.as_ref()).unwrap(), block)
+    }
+
+    fn validators_tx(tx: u8, lock_id: u64, output: Vec<u8>) -> RawTransaction {
+        RawTransaction {
+            tx: Some(Tx::UtxoTx(UnverifiedUtxoTransaction {
+                transaction: Some(UtxoTransaction {
+                    lock_id,
+                    output,
+                    pre_tx_hash: vec![0u8; 33],
+                    ..Default::default()
+                }),
+                transaction_hash: vec![tx; 32],
+                witnesses: vec![],
+            })),
+        }
+    }
+
+    #[tokio::test]
+    async fn validate_header_test() {
+        init_test_crypto();
+        let state = mock_services().await;
+        let _guard = state.lock.lock().await;
+        state.reset();
+
+        let genesis_hash = vec![0; 32];
+        state.set_block(0u64.to_be_bytes().to_vec(), &block(0, vec![], vec![]).1);
+        let mut chain = new_chain(1, 0, genesis_hash.clone(), None);
+        let mut sys_config = chain.get_system_config().await;
+        sys_config.validators = vec![vec![1; 20]];
+        chain.auth.write().await.set_system_config(sys_config);
+
+        // b1 changes validators to 2
+        let change = validators_tx(0xc1, LOCK_ID_VALIDATORS, vec![2; 20]);
+        let (b1, block_b1) = proposed_block(1, genesis_hash.clone(), 10, 1, vec![change]);
+        let (b2, block_b2) = proposed_block(2, b1.clone(), 20, 1, vec![]);
+        let (b3, block_b3) = proposed_block(3, b2.clone(), 30, 2, vec![]);
+        let (b3x, block_b3x) = proposed_block(3, b2.clone(), 30, 3, vec![]);
+
+        // parent unknown yet, checked with finalized validators and timestamp is left to commit
+        assert!(chain.add_remote_proposal(&b2, block_b2).await.unwrap());
+        assert!(chain.add_remote_proposal(&b1, block_b1).await.unwrap());
+        // validators changed by unfinalized b1 are in force after it
+        let (b2x, block_b2x) = proposed_block(2, b1.clone(), 21, 1, vec![]);
+        assert!(matches!(
+            chain.add_remote_proposal(&b2x, block_b2x).await,
+            Err(Error::ProposerNotValidator(_))
+        ));
+        assert!(chain.add_remote_proposal(&b3, block_b3).await.unwrap());
+        assert!(matches!(
+            chain.add_remote_proposal(&b3x, block_b3x).await,
+            Err(Error::ProposerNotValidator(_))
+        ));
+        let (c2, block_c2) = proposed_block(2, b1.clone(), 10, 2, vec![]);
+        assert!(matches!(
+            chain.add_remote_proposal(&c2, block_c2.clone()).await,
+            Err(Error::TimestampNotAfterParent(10, 10))
+        ));
+
+        // committed blocks are checked against parents known by then
+        let (_, block_b1) = proposed_block(
+            1,
+            genesis_hash,
+            10,
+            1,
+            vec![validators_tx(0xc1, LOCK_ID_VALIDATORS, vec![2; 20])],
+        );
+        chain
+            .commit_block(1, &proposal(&block_b1), &[1])
+            .await
+            .unwrap();
+        assert!(matches!(
+            chain.commit_block(2, &proposal(&block_c2), &[2]).await,
+            Err(Error::TimestampNotAfterParent(10, 10))
+        ));
+
+        // a scheduled change of validators is in force once activated
+        let mut chain = new_chain(1, 0, vec![0; 32], None);
+        let scheduled = [1u64.to_be_bytes().to_vec(), vec![4; 20]].concat();
+        let mut sys_config = chain.get_system_config().await;
+        sys_config.validators = vec![vec![1; 20]];
+        let tx = validators_tx(0xc2, LOCK_ID_VALIDATORS | LOCK_ID_SCHEDULED_FLAG, scheduled);
+        if let Some(Tx::UtxoTx(utxo_tx)) = tx.tx {
+            assert!(sys_config.update(&utxo_tx, false));
+        }
+        chain.auth.write().await.set_system_config(sys_config);
+        let (d3, block_d3) = proposed_block(3, vec![2; 32], 30, 4, vec![]);
+        assert!(chain.add_remote_proposal(&d3, block_d3).await.unwrap());
+
+        // validators replaced before the parent are no longer in force
+        state.reset();
+        let (h3, block_h3) = proposed_block(3, vec![2; 32], 30, 5, vec![]);
+        state.set_block(3u64.to_be_bytes().to_vec(), &block_h3);
+        let mut chain = new_chain(2, 3, h3.clone(), None);
+        chain.validator_history = vec![(1, vec![vec![1; 20]]), (3, vec![vec![5; 20]])]
+            .into_iter()
+            .collect();
+        let mut sys_config = chain.get_system_config().await;
+        sys_config.validators = vec![vec![5; 20]];
+        chain.auth.write().await.set_system_config(sys_config);
+        let (e4, block_e4) = proposed_block(4, h3.clone(), 40, 1, vec![]);
+        assert!(matches!(
+            chain.add_remote_proposal(&e4, block_e4).await,
+            Err(Error::ProposerNotValidator(_))
+        ));
+        let (f4, block_f4) = proposed_block(4, h3, 40, 5, vec![]);
+        assert!(chain.add_remote_proposal(&f4, block_f4).await.unwrap());
+
+        // validators of finalized heights are looked up in history
+        let header = |height| BlockHeader {
+            height,
+            ..Default::default()
+        };
+        assert_eq!(
+            chain.validators_at(&header(3)).await.unwrap(),
+            vec![vec![1; 20]]
+        );
+        assert_eq!(
+            chain.validators_at(&header(4)).await.unwrap(),
+            vec![vec![5; 20]]
+        );
+    }
+
+    #[tokio::test]
+    async fn recover_finalization_test() {
+        init_test_crypto();
+        let state = mock_services().await;
+        let _guard = state.lock.lock().await;
+        state.reset();
+
+        let (h3, _) = tx_block(3, vec![2; 32], 3);
+        let (h4, mut b4) = tx_block(4, h3.clone(), 4);
+        b4.proof = vec![4];
+        let mut block_bytes = Vec::new();
+        b4.encode(&mut block_bytes).unwrap();
+        let write = utxo_tx_hash_write(LOCK_ID_VERSION, vec![7; 32]);
+        let finalizing = FinalizingBlock {
+            block: block_bytes,
+            block_hash: h4.clone(),
+            writes: vec![write.clone()],
+        };
+
+        // stopped before anything of block 4 is stored
+        state.set(0, 0, 3u64.to_be_bytes().to_vec());
+        state.set(0, 1, h3.clone());
+        store_finalizing(&finalizing).await.unwrap();
+        recover_finalization().await.unwrap();
+        assert_eq!(state.get(0, 0).unwrap(), 4u64.to_be_bytes().to_vec());
+        assert_eq!(state.get(0, 1).unwrap(), h4);
+        assert_eq!(state.get(0, LOCK_ID_VERSION).unwrap(), write.value);
+        assert_eq!(state.get(0, 2).unwrap(), Vec::<u8>::new());
+        assert_eq!(state.executed_hashes(), vec![h4.clone()]);
+
+        // stopped after height stored, block executed already
+        state.set(0, 1, h3.clone());
+        store_finalizing(&finalizing).await.unwrap();
+        recover_finalization().await.unwrap();
+        assert_eq!(state.get(0, 1).unwrap(), h4);
+        assert_eq!(state.executed_hashes().len(), 1);
+
+        // not following current block, dropped
+        state.reset();
+        state.set(0, 0, 3u64.to_be_bytes().to_vec());
+        state.set(0, 1, vec![3; 32]);
+        store_finalizing(&finalizing).await.unwrap();
+        recover_finalization().await.unwrap();
+        assert_eq!(state.get(0, 0).unwrap(), 3u64.to_be_bytes().to_vec());
+        assert_eq!(state.get(0, LOCK_ID_VERSION), None);
+        assert_eq!(state.get(0, 2).unwrap(), Vec::<u8>::new());
+        assert!(state.executed_hashes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn finalize_exec_failure_test() {
+        init_test_crypto();
+        let state = mock_services().await;
+        let _guard = state.lock.lock().await;
+        state.reset();
+
+        let h3 = vec![3; 32];
+        state.set(0, 0, 3u64.to_be_bytes().to_vec());
+        state.set(0, 1, h3.clone());
+        let mut chain = new_chain(0, 3, h3.clone(), None);
+        let (_, mut b4) = block(4, h3, vec![4]);
+        b4.body = Some(RawTransactions {
+            body: vec![RawTransaction {
+                tx: Some(Tx::UtxoTx(UnverifiedUtxoTransaction {
+                    transaction: Some(UtxoTransaction {
+                        lock_id: LOCK_ID_BLOCK_INTERVAL,
+                        output: vec![0, 0, 0, 9],
+                        pre_tx_hash: vec![0u8; 33],
+                        ..Default::default()
+                    }),
+                    transaction_hash: vec![4; 32],
+                    witnesses: vec![],
+                })),
+            }],
+        });
+        let h4 = get_block_hash(b4.header.as_ref()).unwrap();
+
+        // change of the block is not in force till the block is stored
+        *state.exec_down.lock().unwrap() = true;
+        assert!(matches!(
+            chain.finalize_block(b4.clone(), h4.clone()).await,
+            Err(Error::ExecuteError)
+        ));
+        assert_eq!(chain.get_system_config().await.block_interval, 3);
+        assert_eq!(state.get(0, 3).unwrap(), 4u64.to_be_bytes().to_vec());
+
+        // the block may have been executed, resending it again fails for good
+        assert!(matches!(
+            recover_finalization().await,
+            Err(Error::ReexecuteError(4))
+        ));
+        assert_eq!(state.get(0, 0).unwrap(), 3u64.to_be_bytes().to_vec());
+
+        *state.exec_down.lock().unwrap() = false;
+        recover_finalization().await.unwrap();
+        assert_eq!(state.get(0, 0).unwrap(), 4u64.to_be_bytes().to_vec());
+        assert_eq!(state.get(6, 4).unwrap(), vec![4; 32]);
+        assert_eq!(state.executed_hashes(), vec![h4.clone()]);
+
+        // finalized again by the chain, executed once
+        chain.finalize_block(b4, h4).await.unwrap();
+        assert_eq!(chain.get_system_config().await.block_interval, 9);
+        assert_eq!(state.executed_hashes().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_activation_restart_test() {
+        init_test_crypto();
+        let state = mock_services().await;
+        let _guard = state.lock.lock().await;
+        state.reset();
+
+        let h3 = vec![3; 32];
+        state.set(0, 0, 3u64.to_be_bytes().to_vec());
+        state.set(0, 1, h3.clone());
+        let mut chain = new_chain(0, 3, h3.clone(), None);
+
+        // version 2 scheduled at 4, finalized before
+        let output = [4u64.to_be_bytes().to_vec(), 2u32.to_be_bytes().to_vec()].concat();
+        let tx = validators_tx(0xd1, LOCK_ID_VERSION | LOCK_ID_SCHEDULED_FLAG, output);
+        let mut buf = Vec::new();
+        tx.encode(&mut buf).unwrap();
+        state.set_raw(1, vec![0xd1; 32], buf);
+        state.set(0, LOCK_ID_VERSION | LOCK_ID_SCHEDULED_FLAG, vec![0xd1; 32]);
+        let mut sys_config = chain.get_system_config().await;
+        if let Some(Tx::UtxoTx(utxo_tx)) = tx.tx {
+            assert!(sys_config.update(&utxo_tx, false));
+        }
+        // no longer applies when activated
+        sys_config.version = 2;
+        chain.auth.write().await.set_system_config(sys_config);
+
+        let (h4, b4) = block(4, h3, vec![4]);
+        chain.finalize_block(b4, h4).await.unwrap();
+        let sys_config = chain.get_system_config().await;
+        assert!(sys_config.pending_changes.is_empty());
+        assert_eq!(sys_config.utxo_tx_hashes[&LOCK_ID_VERSION], vec![0u8; 33]);
+        assert_eq!(
+            state.get(0, LOCK_ID_VERSION | LOCK_ID_SCHEDULED_FLAG),
+            Some(vec![])
+        );
+
+        // restart doesn't schedule it again
+        let init_sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        let restored = restore_system_config(init_sys_config).await.unwrap();
+        assert!(restored.pending_changes.is_empty());
+        assert_eq!(restored.utxo_tx_hashes[&LOCK_ID_VERSION], vec![0u8; 33]);
+    }
+
+    #[tokio::test]
+    async fn backfill_utxo_history_test() {
+        let state = mock_services().await;
+        let _guard = state.lock.lock().await;
+        state.reset();
+
+        // finalized utxo tx at height, as storage keeps it
+        let store_utxo_tx = |tx: u8, lock_id: u64, output: Vec<u8>, pre: Vec<u8>, height: u64| {
+            let raw_tx = RawTransaction {
+                tx: Some(Tx::UtxoTx(UnverifiedUtxoTransaction {
+                    transaction: Some(UtxoTransaction {
+                        lock_id,
+                        pre_tx_hash: pre,
+                        output,
+                        ..Default::default()
+                    }),
+                    transaction_hash: vec![tx; 32],
+                    witnesses: vec![],
+                })),
+            };
+            let mut buf = Vec::new();
+            raw_tx.encode(&mut buf).unwrap();
+            state.set_raw(1, vec![tx; 32], buf);
+            state.set_raw(7, vec![tx; 32], height.to_be_bytes().to_vec());
+            state.set_raw(9, vec![tx; 32], 0u64.to_be_bytes().to_vec());
+        };
+        // changed in block 5, scheduled in block 10 to block 20, changed in block 30
+        let scheduled = [20u64.to_be_bytes().to_vec(), vec![2; 20]].concat();
+        store_utxo_tx(1, LOCK_ID_VALIDATORS, vec![1; 20], vec![0; 33], 5);
+        store_utxo_tx(
+            2,
+            LOCK_ID_VALIDATORS | LOCK_ID_SCHEDULED_FLAG,
+            scheduled,
+            vec![1; 32],
+            10,
+        );
+        store_utxo_tx(3, LOCK_ID_VALIDATORS, vec![3; 20], vec![2; 32], 30);
+        state.set(0, LOCK_ID_VALIDATORS, vec![3; 32]);
+        // history kept already
+        let version_history = encode_history_record(7, &[7; 32]);
+        state.set(0, LOCK_ID_VERSION, vec![7; 32]);
+        state.set(
+            0,
+            LOCK_ID_VERSION | LOCK_ID_HISTORY_FLAG,
+            version_history.clone(),
+        );
+
+        let history = [
+            encode_history_record(5, &[1; 32]),
+            encode_history_record(20, &[2; 32]),
+            encode_history_record(30, &[3; 32]),
+        ]
+        .concat();
+        backfill_utxo_history().await.unwrap();
+        assert_eq!(
+            state.get(0, LOCK_ID_VALIDATORS | LOCK_ID_HISTORY_FLAG),
+            Some(history.clone())
+        );
+        assert_eq!(
+            state.get(0, LOCK_ID_VERSION | LOCK_ID_HISTORY_FLAG),
+            Some(version_history)
+        );
+        // lock_id never changed
+        assert_eq!(state.get(0, LOCK_ID_ADMIN | LOCK_ID_HISTORY_FLAG), None);
+
+        // done once
+        backfill_utxo_history().await.unwrap();
+        assert_eq!(
+            state.get(0, LOCK_ID_VALIDATORS | LOCK_ID_HISTORY_FLAG),
+            Some(history)
+        );
+    }
+}
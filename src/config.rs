@@ -12,7 +12,46 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::DEFAULT_PACKAGE_LIMIT;
 use serde_derive::Deserialize;
+use std::collections::HashMap;
+
+/// the order in which pending txs are packaged into a proposal
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PackagePolicy {
+    /// first come, first packaged
+    #[default]
+    Fifo,
+    /// txs from senders in `priority_tags` first, higher priority first
+    Priority,
+    /// utxo txs (system config changes) before normal txs
+    UtxoFirst,
+}
+
+/// which pending tx to drop when the pool is full
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// drop nothing, reject the new tx
+    #[default]
+    Reject,
+    /// drop the earliest arrived normal tx
+    Oldest,
+    /// drop the normal tx with the smallest valid_until_block
+    SoonestExpiry,
+}
+
+/// hash and signature algorithms of the chain
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CryptoSuite {
+    /// sm3 hash and sm2 signature
+    #[default]
+    Sm,
+    /// keccak-256 hash and secp256k1 signature, as ethereum
+    Eth,
+}
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ControllerConfig {
@@ -22,6 +61,45 @@ pub struct ControllerConfig {
     pub kms_port: u16,
     pub executor_port: u16,
     pub block_delay_number: u32,
+    #[serde(default)]
+    pub crypto_suite: CryptoSuite,
+    #[serde(default)]
+    pub package_policy: PackagePolicy,
+    // sender address - priority, only used by priority policy
+    #[serde(default)]
+    pub priority_tags: HashMap<String, u32>,
+    // limits of tx pool, 0 means no limit
+    #[serde(default)]
+    pub pool_tx_limit: usize,
+    #[serde(default)]
+    pub pool_bytes_limit: usize,
+    #[serde(default)]
+    pub pool_sender_quota: usize,
+    #[serde(default)]
+    pub pool_eviction: EvictionPolicy,
+    // file to keep pending txs across restarts, none means not keep
+    #[serde(default)]
+    pub pool_journal: Option<String>,
+    // file to keep committed but unfinalized blocks across restarts, none means not keep
+    #[serde(default)]
+    pub chain_journal: Option<String>,
+    // limits of a block, also checked on remote proposals
+    #[serde(default = "default_package_limit")]
+    pub package_limit: usize,
+    // encoded size of block body, 0 means no limit
+    #[serde(default)]
+    pub max_block_bytes: usize,
+    // sum of quota of normal txs in block, 0 means no limit
+    #[serde(default)]
+    pub max_block_quota: u64,
+    // blocks from this height commit to merkle root of tx hashes, blocks before it may not.
+    // 0 for a new chain, a height not reached yet for nodes of an existing chain to upgrade
+    #[serde(default)]
+    pub merkle_activation_height: u64,
+}
+
+fn default_package_limit() -> usize {
+    DEFAULT_PACKAGE_LIMIT
 }
 
 impl ControllerConfig {
@@ -32,7 +110,7 @@ impl ControllerConfig {
 
 #[cfg(test)]
 mod tests {
-    use super::ControllerConfig;
+    use super::{ControllerConfig, CryptoSuite, EvictionPolicy, PackagePolicy};
 
     #[test]
     fn basic_test() {
@@ -53,5 +131,92 @@ mod tests {
         assert_eq!(config.kms_port, 50005);
         assert_eq!(config.executor_port, 50002);
         assert_eq!(config.block_delay_number, 6);
+        assert_eq!(config.crypto_suite, CryptoSuite::Sm);
+        assert_eq!(config.package_policy, PackagePolicy::Fifo);
+        assert!(config.priority_tags.is_empty());
+        assert_eq!(config.pool_tx_limit, 0);
+        assert_eq!(config.pool_bytes_limit, 0);
+        assert_eq!(config.pool_sender_quota, 0);
+        assert_eq!(config.pool_eviction, EvictionPolicy::Reject);
+        assert_eq!(config.pool_journal, None);
+        assert_eq!(config.chain_journal, None);
+        assert_eq!(config.package_limit, 6000);
+        assert_eq!(config.max_block_bytes, 0);
+        assert_eq!(config.max_block_quota, 0);
+        assert_eq!(config.merkle_activation_height, 0);
+    }
+
+    #[test]
+    fn package_policy_test() {
+        let toml_str = r#"
+        network_port = 50000
+        consensus_port = 50001
+        storage_port = 50003
+        kms_port = 50005
+        executor_port = 50002
+        block_delay_number = 6
+        crypto_suite = "eth"
+        package_policy = "priority"
+
+        [priority_tags]
+        "0xffffffffffffffffffffffffffffffffff020004" = 10
+        "#;
+
+        let config = ControllerConfig::new(toml_str);
+
+        assert_eq!(config.crypto_suite, CryptoSuite::Eth);
+        assert_eq!(config.package_policy, PackagePolicy::Priority);
+        assert_eq!(
+            config
+                .priority_tags
+                .get("0xffffffffffffffffffffffffffffffffff020004"),
+            Some(&10)
+        );
+    }
+
+    #[test]
+    fn pool_limit_test() {
+        let toml_str = r#"
+        network_port = 50000
+        consensus_port = 50001
+        storage_port = 50003
+        kms_port = 50005
+        executor_port = 50002
+        block_delay_number = 6
+        pool_tx_limit = 100000
+        pool_bytes_limit = 67108864
+        pool_sender_quota = 1000
+        pool_eviction = "soonest_expiry"
+        pool_journal = "pool.journal"
+        "#;
+
+        let config = ControllerConfig::new(toml_str);
+
+        assert_eq!(config.pool_tx_limit, 100_000);
+        assert_eq!(config.pool_bytes_limit, 64 * 1024 * 1024);
+        assert_eq!(config.pool_sender_quota, 1000);
+        assert_eq!(config.pool_eviction, EvictionPolicy::SoonestExpiry);
+        assert_eq!(config.pool_journal.as_deref(), Some("pool.journal"));
+    }
+
+    #[test]
+    fn package_limit_test() {
+        let toml_str = r#"
+        network_port = 50000
+        consensus_port = 50001
+        storage_port = 50003
+        kms_port = 50005
+        executor_port = 50002
+        block_delay_number = 6
+        package_limit = 3000
+        max_block_bytes = 4194304
+        max_block_quota = 1073741824
+        "#;
+
+        let config = ControllerConfig::new(toml_str);
+
+        assert_eq!(config.package_limit, 3000);
+        assert_eq!(config.max_block_bytes, 4 * 1024 * 1024);
+        assert_eq!(config.max_block_quota, 1 << 30);
     }
 }
@@ -12,21 +12,27 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::auth::Authentication;
+use crate::auth::{batch_verify_each, Authentication};
 use crate::chain::{Chain, ChainStep};
+use crate::config::ControllerConfig;
 use crate::error::Error;
 use crate::event::EventTask;
+use crate::genesis::GenesisBlock;
+use crate::journal::Journal;
+use crate::merkle::{merkle_proof, MerkleProof, BLOCK_VERSION_MERKLE};
 use crate::node_manager::{
     chain_status_respond::Respond, ChainStatus, ChainStatusInit, ChainStatusRespond, NodeManager,
 };
-use crate::pool::Pool;
+use crate::pool::{order_policy, PackageLimit, Pool, PoolLimit};
 use crate::protocol::sync_manager::{
     SyncBlockRequest, SyncBlockRespond, SyncBlocks, SyncManager, SyncTxRequest, SyncTxRespond,
 };
 use crate::util::*;
-use crate::utxo_set::SystemConfig;
+use crate::utxo_set::{
+    history_tx_hash_at, SystemConfig, LOCK_ID_BUTTON, LOCK_ID_HISTORY_FLAG, LOCK_ID_VERSION,
+};
 use crate::{impl_broadcast, impl_multicast, impl_unicast};
-use crate::{GenesisBlock, DEFAULT_PACKAGE_LIMIT};
+use cita_cloud_proto::blockchain::raw_transaction::Tx::UtxoTx;
 use cita_cloud_proto::{
     blockchain::{Block, CompactBlock, RawTransaction, RawTransactions},
     common::{
@@ -97,36 +103,56 @@ impl From<ControllerMsgType> for &str {
     }
 }
 
+/// Where a tx is in its lifecycle, as seen by this node.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TxStatus {
+    Unknown,
+    /// in pool, waiting to be packaged
+    Pending,
+    /// in an unfinalized main_chain block at height
+    Unfinalized(u64),
+    /// finalized at height and index
+    Finalized(u64, u64),
+    /// dropped from pool as expired
+    Expired,
+}
+
 #[derive(Clone)]
 pub struct Controller {
     auth: Arc<RwLock<Authentication>>,
 
     pool: Arc<RwLock<Pool>>,
 
-    pub(crate) chain: Arc<RwLock<Chain>>,
+    pub chain: Arc<RwLock<Chain>>,
 
-    pub(crate) local_address: Address,
+    pub local_address: Address,
 
     current_status: Arc<RwLock<ChainStatus>>,
 
     global_status: Arc<RwLock<(Address, ChainStatus)>>,
 
-    pub(crate) node_manager: NodeManager,
+    pub node_manager: NodeManager,
 
-    pub(crate) sync_manager: SyncManager,
+    pub sync_manager: SyncManager,
 
     task_sender: mpsc::UnboundedSender<EventTask>,
     // sync state flag
     is_sync: Arc<RwLock<bool>>,
+
+    pool_journal: Option<String>,
+
+    // sys_config of init_sys_config.toml, base of historical sys_config
+    init_sys_config: SystemConfig,
 }
 
 impl Controller {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
-        block_delay_number: u32,
+        config: &ControllerConfig,
         current_block_number: u64,
         current_block_hash: Vec<u8>,
         sys_config: SystemConfig,
+        init_sys_config: SystemConfig,
         genesis: GenesisBlock,
         key_id: u64,
         node_address: Vec<u8>,
@@ -149,17 +175,30 @@ impl Controller {
             }),
         };
 
+        let mut pool = Pool::new(
+            PackageLimit::new(config),
+            order_policy(config),
+            PoolLimit::new(config),
+        );
+        pool.set_block_limit(sys_config.block_limit);
+        let pool = Arc::new(RwLock::new(pool));
+        let validators = sys_config.validators.clone();
         let auth = Arc::new(RwLock::new(Authentication::new(sys_config)));
-        let pool = Arc::new(RwLock::new(Pool::new(DEFAULT_PACKAGE_LIMIT)));
         let chain = Arc::new(RwLock::new(Chain::new(
-            block_delay_number,
+            config.block_delay_number,
+            config.merkle_activation_height,
             current_block_number,
             current_block_hash,
             pool.clone(),
             auth.clone(),
             genesis,
+            validators,
             key_id,
             node_address.clone(),
+            config
+                .chain_journal
+                .as_ref()
+                .map(|path| Journal::open(path).expect("open chain journal failed")),
         )));
 
         Controller {
@@ -180,14 +219,22 @@ impl Controller {
             sync_manager: SyncManager::default(),
             task_sender,
             is_sync: Arc::new(RwLock::new(false)),
+            pool_journal: config.pool_journal.clone(),
+            init_sys_config,
         }
     }
 
     pub async fn init(&self, init_block_number: u64, sys_config: SystemConfig) {
         {
-            let chain = self.chain.write().await;
+            let mut chain = self.chain.write().await;
             chain.init(init_block_number).await;
-            chain.init_auth(init_block_number).await;
+            chain
+                .init_auth(init_block_number, self.init_sys_config.block_limit)
+                .await;
+            chain.restore_main_chain();
+        }
+        if let Some(path) = self.pool_journal.as_ref() {
+            self.replay_pool_journal(path).await;
         }
         let status = self
             .init_status(init_block_number, sys_config)
@@ -205,6 +252,66 @@ impl Controller {
         .unwrap();
     }
 
+    // restore pending txs saved before restart, txs expired or committed meanwhile are dropped
+    async fn replay_pool_journal(&self, path: &str) {
+        let journal = Journal::open(path).expect("open pool journal failed");
+        let raw_txs: Vec<RawTransaction> = journal.load().unwrap_or_else(|e| {
+            warn!("load pool journal failed: {}", e);
+            Vec::new()
+        });
+        let total = raw_txs.len();
+
+        // txs in unfinalized blocks restored before would be packaged again
+        let raw_txs: Vec<RawTransaction> = {
+            let chain = self.chain.read().await;
+            let auth = self.auth.read().await;
+            raw_txs
+                .into_iter()
+                .filter(|raw_tx| match get_tx_hash(raw_tx) {
+                    Ok(tx_hash) if chain.check_dup_tx(&tx_hash) => {
+                        log::debug!(
+                            "replay_pool_journal: drop tx(0x{}): in main_chain",
+                            hex::encode(&tx_hash)
+                        );
+                        false
+                    }
+                    Ok(_) => match auth.precheck_raw_tx(raw_tx) {
+                        Ok(()) => true,
+                        Err(reason) => {
+                            log::debug!("replay_pool_journal: drop tx: {}", reason);
+                            false
+                        }
+                    },
+                    Err(e) => {
+                        log::debug!("replay_pool_journal: drop tx: {}", e);
+                        false
+                    }
+                })
+                .collect()
+        };
+        let results = batch_verify_each(raw_txs.clone())
+            .await
+            .expect("verify pool journal failed");
+
+        let mut pool = self.pool.write().await;
+        for (raw_tx, result) in raw_txs.into_iter().zip(results) {
+            let ret = match result {
+                Ok(tx_hash) => pool.enqueue(tx_hash, raw_tx),
+                Err(reason) => Err(Error::TxRejected(reason)),
+            };
+            if let Err(e) = ret {
+                log::debug!("replay_pool_journal: drop tx: {}", e);
+            }
+        }
+        log::info!(
+            "replay_pool_journal: restore {} of {} txs from {}",
+            pool.len(),
+            total,
+            path
+        );
+        pool.set_journal(journal);
+    }
+
     pub async fn rpc_get_block_number(&self, is_pending: bool) -> Result<u64, String> {
         let chain = self.chain.read().await;
         let block_number = chain.get_block_number(is_pending);
@@ -233,23 +340,28 @@ impl Controller {
             let auth = self.auth.read().await;
             auth.check_raw_tx(raw_tx.clone())
                 .await
-                .map_err(Error::ExpectError)?;
+                .map_err(Error::TxRejected)?;
         };
 
-        if {
+        let ret = {
             let mut pool = self.pool.write().await;
             pool.enqueue(tx_hash.clone(), raw_tx.clone())
-        } {
-            if broadcast {
-                self.multicast_send_tx(raw_tx).await;
+        };
+        match ret {
+            Ok(()) => {
+                if broadcast {
+                    self.multicast_send_tx(raw_tx).await;
+                }
+                Ok(tx_hash)
+            }
+            Err(e) => {
+                warn!(
+                    "rpc_send_raw_transaction: enqueue tx(0x{}) failed: {}",
+                    hex::encode(&tx_hash),
+                    e
+                );
+                Err(e)
             }
-            Ok(tx_hash)
-        } else {
-            warn!(
-                "rpc_send_raw_transaction: found dup tx(0x{}) in pool",
-                hex::encode(&tx_hash)
-            );
-            Err(Error::DupTransaction(tx_hash.to_vec()))
         }
     }
 
@@ -262,11 +374,38 @@ impl Controller {
 
         let mut pool = self.pool.write().await;
         for raw_tx in raw_txs.body {
-            pool.enqueue(get_tx_hash(&raw_tx)?, raw_tx);
+            match pool.enqueue(get_tx_hash(&raw_tx)?, raw_tx) {
+                // broadcast txs come again from other peers
+                Err(e @ Error::DupTransaction(_)) => log::debug!("batch_transactions: {}", e),
+                Err(e) => warn!("batch_transactions: {}", e),
+                Ok(()) => {}
+            }
         }
         Ok(())
     }
 
+    pub async fn rpc_get_pool_status(&self, limit: usize) -> (usize, Vec<Vec<u8>>, u64) {
+        let pool = self.pool.read().await;
+        (
+            pool.len(),
+            pool.pool_get_hashes(limit),
+            pool.expired_count(),
+        )
+    }
+
+    pub async fn rpc_get_pending_tx(&self, tx_hash: &[u8]) -> Result<RawTransaction, Error> {
+        let pool = self.pool.read().await;
+        pool.pool_get_tx(tx_hash).ok_or(Error::NoTransaction)
+    }
+
+    pub async fn rpc_get_pending_txs_by_sender(
+        &self,
+        sender: &[u8],
+    ) -> Vec<(Vec<u8>, RawTransaction)> {
+        let pool = self.pool.read().await;
+        pool.pool_get_txs_by_sender(sender)
+    }
+
     pub async fn rpc_get_block_by_hash(&self, hash: Vec<u8>) -> Result<CompactBlock, Error> {
         let block_number = load_data(8, hash.clone())
             .await
@@ -303,6 +442,48 @@ impl Controller {
         load_tx_info(&tx_hash).await.map(|t| t.0)
     }
 
+    // merkle proof of a finalized tx, with height and transactions_root of its block
+    pub async fn rpc_get_tx_proof(
+        &self,
+        tx_hash: &[u8],
+    ) -> Result<(u64, Vec<u8>, MerkleProof), Error> {
+        let (height, tx_index) = load_tx_info(tx_hash).await?;
+        let compact_block = get_compact_block(height).await?.0;
+        if compact_block.version < BLOCK_VERSION_MERKLE {
+            return Err(Error::NoMerkleRoot(height));
+        }
+        let transactions_root = compact_block
+            .header
+            .ok_or(Error::NoneBlockHeader)?
+            .transactions_root;
+        let tx_hashes = compact_block.body.ok_or(Error::NoneBlockBody)?.tx_hashes;
+        let proof = merkle_proof(&tx_hashes, tx_index as usize).ok_or(Error::NoTxIndex)?;
+        Ok((height, transactions_root, proof))
+    }
+
+    pub async fn rpc_get_tx_status(&self, tx_hash: &[u8]) -> TxStatus {
+        let expired = {
+            // hold chain so the tx can't move from pool into main_chain between the checks
+            let chain = self.chain.read().await;
+            if let Some(height) = chain.get_pending_tx_height(tx_hash) {
+                return TxStatus::Unfinalized(height);
+            }
+            let pool = self.pool.read().await;
+            if pool.is_contain(tx_hash) {
+                return TxStatus::Pending;
+            }
+            pool.is_expired(tx_hash)
+        };
+
+        if let Ok((height, index)) = load_tx_info(tx_hash).await {
+            TxStatus::Finalized(height, index)
+        } else if expired {
+            TxStatus::Expired
+        } else {
+            TxStatus::Unknown
+        }
+    }
+
     pub async fn rpc_get_tx_index(&self, tx_hash: Vec<u8>) -> Result<u64, Error> {
         load_tx_info(&tx_hash).await.map(|t| t.1)
     }
@@ -328,6 +509,31 @@ impl Controller {
         Ok(sys_config)
     }
 
+    // sys_config in force after block of height finalized
+    pub async fn rpc_get_system_config_at(&self, height: u64) -> Result<SystemConfig, Error> {
+        let block_number = self.rpc_get_block_number(false).await.unwrap();
+        if height > block_number {
+            return Err(Error::NoBlock(height));
+        }
+
+        let mut sys_config = self.init_sys_config.clone();
+        for lock_id in LOCK_ID_VERSION..LOCK_ID_BUTTON {
+            let key = (lock_id | LOCK_ID_HISTORY_FLAG).to_be_bytes().to_vec();
+            let history = load_data_maybe_empty(0, key)
+                .await
+                .map_err(Error::InternalError)?;
+            if let Some(tx_hash) = history_tx_hash_at(&history, height) {
+                match db_get_tx(&tx_hash).await?.tx {
+                    Some(UtxoTx(utxo_tx)) => {
+                        sys_config.update(&utxo_tx, true);
+                    }
+                    _ => return Err(Error::ExpectError("tx is not utxo_tx".to_owned())),
+                }
+            }
+        }
+        Ok(sys_config)
+    }
+
     pub async fn chain_get_proposal(&self) -> Result<(u64, Vec<u8>), Error> {
         let mut chain = self.chain.write().await;
         chain
@@ -780,12 +986,16 @@ impl Controller {
                     }
                     {
                         let mut wr = controller_clone.chain.write().await;
-                        if !wr
-                            .add_remote_proposal(&block_hash, full_block)
-                            .await
-                            .unwrap()
-                        {
-                            warn!("add remote proposal: 0x{} failed", hex::encode(&block_hash))
+                        match wr.add_remote_proposal(&block_hash, full_block).await {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                warn!("add remote proposal: 0x{} failed", hex::encode(&block_hash))
+                            }
+                            Err(e) => warn!(
+                                "add remote proposal: 0x{} failed: {}",
+                                hex::encode(&block_hash),
+                                e
+                            ),
                         }
                     }
                 });
@@ -0,0 +1,142 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::CryptoSuite;
+use crate::error::Error;
+use crate::util::{ADDR_BYTES_LEN, HASH_BYTES_LEN};
+use tiny_keccak::{Hasher, Keccak};
+
+pub const SM2_SIGNATURE_BYTES_LEN: usize = 128;
+pub const SECP256K1_SIGNATURE_BYTES_LEN: usize = 65;
+
+/// Hash and signature algorithms of a chain, all nodes of a chain must use the same suite.
+pub trait Crypto: Send + Sync {
+    fn hash(&self, data: &[u8]) -> Vec<u8>;
+
+    fn signature_len(&self) -> usize;
+
+    /// Recover public key of the signer, signature len is checked by caller.
+    fn recover(&self, signature: &[u8], message: &[u8]) -> Result<Vec<u8>, Error>;
+
+    fn pk2address(&self, pk: &[u8]) -> Vec<u8> {
+        self.hash(pk)[HASH_BYTES_LEN - ADDR_BYTES_LEN..].to_vec()
+    }
+}
+
+/// SM3 hash, SM2 signature is r, s and public key.
+pub struct Sm;
+
+impl Crypto for Sm {
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        libsm::sm3::hash::Sm3Hash::new(data).get_hash().to_vec()
+    }
+
+    fn signature_len(&self) -> usize {
+        SM2_SIGNATURE_BYTES_LEN
+    }
+
+    fn recover(&self, signature: &[u8], message: &[u8]) -> Result<Vec<u8>, Error> {
+        let r = &signature[0..32];
+        let s = &signature[32..64];
+        let pk = &signature[64..];
+
+        let signature =
+            efficient_sm2::Signature::new(r, s).map_err(|e| Error::ExpectError(e.to_string()))?;
+        let public_key = efficient_sm2::PublicKey::new(&pk[..32], &pk[32..]);
+
+        signature.verify(&public_key, message).map_or_else(
+            |e| Err(Error::ExpectError(e.to_string())),
+            |_| Ok(pk.to_vec()),
+        )
+    }
+}
+
+/// Keccak-256 hash, secp256k1 signature is r, s and recovery id, as Ethereum.
+pub struct Eth;
+
+impl Crypto for Eth {
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = Keccak::v256();
+        hasher.update(data);
+        let mut output = [0u8; HASH_BYTES_LEN];
+        hasher.finalize(&mut output);
+        output.to_vec()
+    }
+
+    fn signature_len(&self) -> usize {
+        SECP256K1_SIGNATURE_BYTES_LEN
+    }
+
+    fn recover(&self, signature: &[u8], message: &[u8]) -> Result<Vec<u8>, Error> {
+        let message = libsecp256k1::Message::parse_slice(message)
+            .map_err(|e| Error::ExpectError(e.to_string()))?;
+        let sig = libsecp256k1::Signature::parse_standard_slice(&signature[..64])
+            .map_err(|e| Error::ExpectError(e.to_string()))?;
+        // both 0/1 and 27/28 are in use
+        let v = signature[64];
+        let recovery_id = libsecp256k1::RecoveryId::parse(if v >= 27 { v - 27 } else { v })
+            .map_err(|e| Error::ExpectError(e.to_string()))?;
+
+        let pk = libsecp256k1::recover(&message, &sig, &recovery_id)
+            .map_err(|e| Error::ExpectError(e.to_string()))?;
+        // drop the 0x04 prefix of uncompressed key
+        Ok(pk.serialize()[1..].to_vec())
+    }
+}
+
+pub fn crypto_suite(suite: CryptoSuite) -> Box<dyn Crypto> {
+    match suite {
+        CryptoSuite::Sm => Box::new(Sm),
+        CryptoSuite::Eth => Box::new(Eth),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Crypto, Eth};
+
+    #[test]
+    fn eth_hash_test() {
+        assert_eq!(
+            hex::encode(Eth.hash(&[])),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+    }
+
+    #[test]
+    fn eth_recover_test() {
+        let sk = libsecp256k1::SecretKey::parse(&[0x11; 32]).unwrap();
+        let pk = libsecp256k1::PublicKey::from_secret_key(&sk);
+        let tx_hash = Eth.hash(b"tx");
+
+        let message = libsecp256k1::Message::parse_slice(&tx_hash).unwrap();
+        let (sig, recovery_id) = libsecp256k1::sign(&message, &sk);
+        let mut signature = sig.serialize().to_vec();
+        signature.push(recovery_id.serialize() + 27);
+
+        let recovered = Eth.recover(&signature, &tx_hash).unwrap();
+        assert_eq!(recovered, pk.serialize()[1..].to_vec());
+        assert_eq!(
+            Eth.pk2address(&recovered),
+            Eth.hash(&pk.serialize()[1..])[12..].to_vec()
+        );
+
+        // signature of another message recovers another key
+        let recovered = Eth.recover(&signature, &Eth.hash(b"other")).ok();
+        assert_ne!(recovered, Some(pk.serialize()[1..].to_vec()));
+
+        signature[64] = 5;
+        assert!(Eth.recover(&signature, &tx_hash).is_err());
+    }
+}
@@ -12,6 +12,136 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use tonic::Code;
+
+/// Why a tx is rejected by check_raw_tx
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RejectReason {
+    /// tx, transaction or witness is none
+    InvalidRawTx,
+
+    /// emergency brake is on
+    EmergencyBrake,
+
+    /// sender in denylist or not in allowlist
+    SenderNotPermitted,
+
+    /// utxo tx signer is not admin
+    NotAdmin,
+
+    /// utxo tx witnesses less than threshold or dup
+    InvalidWitnesses,
+
+    InvalidVersion,
+
+    InvalidTo,
+
+    InvalidNonce,
+
+    InvalidValidUntilBlock,
+
+    InvalidValue,
+
+    InvalidChainId,
+
+    InvalidLockId,
+
+    /// scheduled change not activated after current height
+    InvalidActivationHeight,
+
+    /// utxo tx output malformed for its lock_id
+    InvalidOutput,
+
+    /// lock_id has a scheduled change not activated yet
+    ChangePending,
+
+    /// utxo tx not based on current config
+    InvalidPreTxHash,
+
+    /// tx hash len or content not correct
+    InvalidHash,
+
+    /// signature len not correct or recover failed
+    InvalidSignature,
+
+    /// signer not consistent with witness sender
+    InvalidSender,
+
+    /// tx already on chain
+    DupTransaction,
+
+    EncodeError,
+}
+
+impl RejectReason {
+    /// machine readable reason, never change it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::InvalidRawTx => "INVALID_RAW_TX",
+            RejectReason::EmergencyBrake => "EMERGENCY_BRAKE",
+            RejectReason::SenderNotPermitted => "SENDER_NOT_PERMITTED",
+            RejectReason::NotAdmin => "NOT_ADMIN",
+            RejectReason::InvalidWitnesses => "INVALID_WITNESSES",
+            RejectReason::InvalidVersion => "INVALID_VERSION",
+            RejectReason::InvalidTo => "INVALID_TO",
+            RejectReason::InvalidNonce => "INVALID_NONCE",
+            RejectReason::InvalidValidUntilBlock => "INVALID_VALID_UNTIL_BLOCK",
+            RejectReason::InvalidValue => "INVALID_VALUE",
+            RejectReason::InvalidChainId => "INVALID_CHAIN_ID",
+            RejectReason::InvalidLockId => "INVALID_LOCK_ID",
+            RejectReason::InvalidActivationHeight => "INVALID_ACTIVATION_HEIGHT",
+            RejectReason::InvalidOutput => "INVALID_OUTPUT",
+            RejectReason::ChangePending => "CHANGE_PENDING",
+            RejectReason::InvalidPreTxHash => "INVALID_PRE_TX_HASH",
+            RejectReason::InvalidHash => "INVALID_HASH",
+            RejectReason::InvalidSignature => "INVALID_SIGNATURE",
+            RejectReason::InvalidSender => "INVALID_SENDER",
+            RejectReason::DupTransaction => "DUP_TRANSACTION",
+            RejectReason::EncodeError => "ENCODE_ERROR",
+        }
+    }
+
+    pub fn code(&self) -> Code {
+        match self {
+            RejectReason::DupTransaction => Code::AlreadyExists,
+            RejectReason::EmergencyBrake
+            | RejectReason::InvalidPreTxHash
+            | RejectReason::ChangePending => Code::FailedPrecondition,
+            RejectReason::SenderNotPermitted | RejectReason::NotAdmin => Code::PermissionDenied,
+            RejectReason::EncodeError => Code::Internal,
+            _ => Code::InvalidArgument,
+        }
+    }
+}
+
+impl ::std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        match self {
+            RejectReason::InvalidRawTx => write!(f, "Invalid raw tx"),
+            RejectReason::EmergencyBrake => write!(f, "Emergency brake is on"),
+            RejectReason::SenderNotPermitted => write!(f, "Sender not permitted"),
+            RejectReason::NotAdmin => write!(f, "Utxo tx must be signed by admin"),
+            RejectReason::InvalidWitnesses => write!(f, "Invalid witnesses"),
+            RejectReason::InvalidVersion => write!(f, "Invalid version"),
+            RejectReason::InvalidTo => write!(f, "Invalid to"),
+            RejectReason::InvalidNonce => write!(f, "Invalid nonce"),
+            RejectReason::InvalidValidUntilBlock => write!(f, "Invalid valid_until_block"),
+            RejectReason::InvalidValue => write!(f, "Invalid value"),
+            RejectReason::InvalidChainId => write!(f, "Invalid chain_id"),
+            RejectReason::InvalidLockId => write!(f, "Invalid lock_id"),
+            RejectReason::InvalidActivationHeight => write!(f, "Invalid activation height"),
+            RejectReason::InvalidOutput => write!(f, "Invalid output"),
+            RejectReason::ChangePending => write!(f, "Lock_id has a pending change"),
+            RejectReason::InvalidPreTxHash => write!(f, "Invalid pre_tx_hash"),
+            RejectReason::InvalidHash => write!(f, "Invalid tx hash"),
+            RejectReason::InvalidSignature => write!(f, "Invalid signature"),
+            RejectReason::InvalidSender => write!(f, "Invalid sender"),
+            RejectReason::DupTransaction => write!(f, "Dup transaction"),
+            RejectReason::EncodeError => write!(f, "Encode tx failed"),
+        }
+    }
+}
+
 /// The error types todo reorganize to different module
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -37,6 +167,9 @@ pub enum Error {
     /// not get the proof
     NoProof,
 
+    /// block has no merkle transactions_root
+    NoMerkleRoot(u64),
+
     /// not get height of block which wrap tx
     NoTxHeight,
 
@@ -73,6 +206,9 @@ pub enum Error {
     /// execute error
     ExecuteError,
 
+    /// resending block of the height to executor failed, it may have been executed
+    ReexecuteError(u64),
+
     /// proto struct encode error
     EncodeError(String),
 
@@ -91,12 +227,30 @@ pub enum Error {
     /// find dup transaction
     DupTransaction(Vec<u8>),
 
+    /// tx pool reach its count or bytes limit
+    PoolFull,
+
+    /// sender has too many txs in pool
+    SenderQuotaExceeded(Vec<u8>),
+
+    /// tx rejected by check_raw_tx
+    TxRejected(RejectReason),
+
     /// proposal too high
     ProposalTooHigh(u64, u64),
 
     /// proposal too low
     ProposalTooLow(u64, u64),
 
+    /// proposal has more txs than package limit
+    ProposalTooManyTxs(usize, usize),
+
+    /// encoded block body of proposal exceeds bytes limit
+    ProposalTooLarge(usize, usize),
+
+    /// sum of tx quota in proposal exceeds quota limit
+    ProposalQuotaExceeded(u64, u64),
+
     /// proposal check error
     ProposalCheckError,
 
@@ -106,6 +260,21 @@ pub enum Error {
     /// block hash check error
     BlockCheckError,
 
+    /// transactions_root of block(height) doesn't match its body
+    TransactionsRootMismatch(u64),
+
+    /// block(height) of version before merkle root at or after merkle_activation_height
+    LegacyBlockVersion(u64, u32),
+
+    /// proposer of block is not a validator
+    ProposerNotValidator(Vec<u8>),
+
+    /// block timestamp not after timestamp of its parent
+    TimestampNotAfterParent(u64, u64),
+
+    /// block timestamp too far ahead of local time
+    TimestampInFuture(u64, u64),
+
     /// the sig of chain status init check error
     CSISigCheckError,
 
@@ -142,6 +311,9 @@ impl ::std::fmt::Display for Error {
             Error::NoProvideAddress => write!(f, "No correct address provide"),
             Error::NoBlock(h) => write!(f, "Not get the {}th block", h),
             Error::NoProof => write!(f, "Not get the proof"),
+            Error::NoMerkleRoot(h) => {
+                write!(f, "The {}th block has no merkle transactions root", h)
+            }
             Error::NoTxHeight => write!(f, "Not get the height of block which wrap tx"),
             Error::NoTxIndex => write!(f, "Not get tx index"),
             Error::NoTransaction => write!(f, "Not get transaction"),
@@ -154,6 +326,11 @@ impl ::std::fmt::Display for Error {
             Error::EarlyStatus => write!(f, "Receive early status from same node"),
             Error::StoreError => write!(f, "Store data error"),
             Error::ExecuteError => write!(f, "Execute error"),
+            Error::ReexecuteError(h) => write!(
+                f,
+                "Resend the {}th block to executor failed, it may have been executed",
+                h
+            ),
             Error::EncodeError(s) => write!(f, "Proto struct encode error: {}", s),
             Error::DecodeError(s) => write!(f, "Proto struct decode error: {}", s),
             Error::NoCandidate => write!(f, "No candidate block"),
@@ -168,13 +345,56 @@ impl ::std::fmt::Display for Error {
                 "Proposal(h: {}) is lower than current(h: {})",
                 proposal, current
             ),
+            Error::ProposalTooManyTxs(count, limit) => {
+                write!(f, "Proposal has {} txs, more than limit {}", count, limit)
+            }
+            Error::ProposalTooLarge(bytes, limit) => write!(
+                f,
+                "Proposal body is {} bytes, larger than limit {}",
+                bytes, limit
+            ),
+            Error::ProposalQuotaExceeded(quota, limit) => write!(
+                f,
+                "Proposal uses quota {}, more than limit {}",
+                quota, limit
+            ),
             Error::ProposalCheckError => write!(f, "Proposal check error"),
             Error::ConsensusProposalCheckError => write!(f, "consensus proposal check error"),
             Error::NoForkTree => write!(f, "Fork tree no block"),
             Error::DupTransaction(h) => {
                 write!(f, "Found dup transaction 0x{}", hex::encode(h))
             }
+            Error::PoolFull => write!(f, "Transaction pool is full"),
+            Error::SenderQuotaExceeded(sender) => write!(
+                f,
+                "Sender 0x{} has too many transactions in pool",
+                hex::encode(sender)
+            ),
+            Error::TxRejected(reason) => write!(f, "Transaction rejected: {}", reason),
             Error::BlockCheckError => write!(f, "block hash check error"),
+            Error::TransactionsRootMismatch(h) => write!(
+                f,
+                "Transactions root of the {}th block mismatches its body",
+                h
+            ),
+            Error::LegacyBlockVersion(h, version) => write!(
+                f,
+                "Version {} of the {}th block has no merkle root of txs",
+                version, h
+            ),
+            Error::ProposerNotValidator(proposer) => {
+                write!(f, "Proposer 0x{} is not a validator", hex::encode(proposer))
+            }
+            Error::TimestampNotAfterParent(timestamp, parent) => write!(
+                f,
+                "Block timestamp {} is not after parent timestamp {}",
+                timestamp, parent
+            ),
+            Error::TimestampInFuture(timestamp, now) => write!(
+                f,
+                "Block timestamp {} is too far ahead of local time {}",
+                timestamp, now
+            ),
             Error::CSISigCheckError => write!(f, "The sig of chain status init check error"),
             Error::VersionOrIdCheckError => write!(f, "Chain version or chain id check error"),
             Error::HashCheckError => write!(f, "Hash check error"),
@@ -185,3 +405,68 @@ impl ::std::fmt::Display for Error {
         }
     }
 }
+
+impl Error {
+    /// machine readable reason of rejecting a tx
+    pub fn reject_reason(&self) -> Option<&'static str> {
+        match self {
+            Error::TxRejected(reason) => Some(reason.as_str()),
+            Error::DupTransaction(_) => Some(RejectReason::DupTransaction.as_str()),
+            Error::PoolFull => Some("POOL_FULL"),
+            Error::SenderQuotaExceeded(_) => Some("SENDER_QUOTA_EXCEEDED"),
+            _ => None,
+        }
+    }
+
+    /// grpc code when a tx is rejected for this error
+    pub fn reject_code(&self) -> Code {
+        match self {
+            Error::TxRejected(reason) => reason.code(),
+            Error::DupTransaction(_) => Code::AlreadyExists,
+            Error::PoolFull | Error::SenderQuotaExceeded(_) => Code::ResourceExhausted,
+            _ => Code::InvalidArgument,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, RejectReason};
+    use tonic::Code;
+
+    #[test]
+    fn reject_code_test() {
+        for (e, code, reason) in [
+            (
+                Error::TxRejected(RejectReason::DupTransaction),
+                Code::AlreadyExists,
+                Some("DUP_TRANSACTION"),
+            ),
+            (
+                Error::DupTransaction(vec![1; 32]),
+                Code::AlreadyExists,
+                Some("DUP_TRANSACTION"),
+            ),
+            (
+                Error::TxRejected(RejectReason::EmergencyBrake),
+                Code::FailedPrecondition,
+                Some("EMERGENCY_BRAKE"),
+            ),
+            (
+                Error::TxRejected(RejectReason::NotAdmin),
+                Code::PermissionDenied,
+                Some("NOT_ADMIN"),
+            ),
+            (
+                Error::TxRejected(RejectReason::InvalidVersion),
+                Code::InvalidArgument,
+                Some("INVALID_VERSION"),
+            ),
+            (Error::PoolFull, Code::ResourceExhausted, Some("POOL_FULL")),
+            (Error::NoneBlockBody, Code::InvalidArgument, None),
+        ] {
+            assert_eq!(e.reject_code(), code);
+            assert_eq!(e.reject_reason(), reason);
+        }
+    }
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use crate::util::{clean_0x, hash_data};
+use crate::utxo_set::SystemConfig;
 use cita_cloud_proto::blockchain::{Block, BlockHeader, RawTransactions};
 use prost::Message;
 use serde_derive::Deserialize;
@@ -21,21 +22,34 @@ use serde_derive::Deserialize;
 pub struct GenesisBlock {
     pub timestamp: u64,
     pub prevhash: String,
+    // commit to init sys_config in genesis block, changes genesis block hash of existing chains
+    #[serde(default)]
+    pub commit_sys_config: bool,
+    // hash of init sys_config, committed as transactions_root of genesis block if set above
+    #[serde(skip)]
+    pub sys_config_hash: Vec<u8>,
 }
 
 impl GenesisBlock {
-    pub fn new(genesis_block_str: &str) -> Self {
-        toml::from_str::<GenesisBlock>(genesis_block_str)
-            .expect("Error while parsing genesis_block_str")
+    pub fn new(genesis_block_str: &str, init_sys_config: &SystemConfig) -> Self {
+        let mut genesis = toml::from_str::<GenesisBlock>(genesis_block_str)
+            .expect("Error while parsing genesis_block_str");
+        genesis.sys_config_hash = init_sys_config.init_hash();
+        genesis
     }
     pub fn genesis_block(&self) -> Block {
         let prev_hash =
             hex::decode(clean_0x(&self.prevhash)).expect("parsing prevhash in genesis failed!");
+        let transactions_root = if self.commit_sys_config {
+            self.sys_config_hash.clone()
+        } else {
+            vec![0u8; 32]
+        };
         let header = BlockHeader {
             prevhash: prev_hash,
             timestamp: self.timestamp,
             height: 0,
-            transactions_root: vec![0u8; 32],
+            transactions_root,
             proposer: vec![0u8; 32],
         };
         Block {
@@ -62,18 +76,57 @@ impl GenesisBlock {
 #[cfg(test)]
 mod tests {
     use super::GenesisBlock;
+    use crate::util::init_test_crypto;
+    use crate::utxo_set::SystemConfig;
 
     #[test]
     fn basic_test() {
+        init_test_crypto();
         let toml_str = r#"
         timestamp = 123456
-        prev_hash = "0x010203040506"
+        prevhash = "0x010203040506"
+        commit_sys_config = true
         "#;
 
-        let genesis = GenesisBlock::new(toml_str);
+        let sys_config = SystemConfig::new(0, vec![1; 32], vec![2; 20], 3, vec![vec![4; 20]]);
+        let genesis = GenesisBlock::new(toml_str, &sys_config);
         let block = genesis.genesis_block();
         let header = block.header.unwrap();
         assert_eq!(header.timestamp, 123_456);
         assert_eq!(header.prevhash, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(header.transactions_root, sys_config.init_hash());
+
+        // another init sys_config, another genesis
+        let mut other = sys_config.clone();
+        other.validators.push(vec![5; 20]);
+        let other_genesis = GenesisBlock::new(toml_str, &other);
+        assert_ne!(
+            genesis.genesis_block_hash(),
+            other_genesis.genesis_block_hash()
+        );
+    }
+
+    #[test]
+    fn legacy_genesis_test() {
+        init_test_crypto();
+        let toml_str = r#"
+        timestamp = 123456
+        prevhash = "0x010203040506"
+        "#;
+
+        // without commit_sys_config the genesis block is the same for any init sys_config
+        let sys_config = SystemConfig::new(0, vec![1; 32], vec![2; 20], 3, vec![vec![4; 20]]);
+        let genesis = GenesisBlock::new(toml_str, &sys_config);
+        assert!(!genesis.commit_sys_config);
+        let header = genesis.genesis_block().header.unwrap();
+        assert_eq!(header.transactions_root, vec![0u8; 32]);
+
+        let mut other = sys_config;
+        other.validators.push(vec![5; 20]);
+        let other_genesis = GenesisBlock::new(toml_str, &other);
+        assert_eq!(
+            genesis.genesis_block_hash(),
+            other_genesis.genesis_block_hash()
+        );
     }
 }
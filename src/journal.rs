@@ -0,0 +1,164 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use log::warn;
+use prost::Message;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Append-only file of proto messages, each record is a 4 bytes big endian length and the
+/// encoded message. A record torn by crash is ignored on load and dropped by next rewrite.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Journal { path, file })
+    }
+
+    pub fn append<M: Message>(&mut self, msg: &M) -> io::Result<()> {
+        self.file.write_all(&encode_record(msg))
+    }
+
+    /// replace all records with `msgs`
+    pub fn rewrite<'a, M, I>(&mut self, msgs: I) -> io::Result<()>
+    where
+        M: Message + 'a,
+        I: IntoIterator<Item = &'a M>,
+    {
+        let mut buf = Vec::new();
+        for msg in msgs {
+            buf.extend_from_slice(&encode_record(msg));
+        }
+
+        // write to a temp file then rename, so a crash leaves either the old or the new journal
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            tmp.write_all(&buf)?;
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
+    pub fn load<M: Message + Default>(&self) -> io::Result<Vec<M>> {
+        let data = fs::read(&self.path)?;
+        let mut msgs = Vec::new();
+        let mut pos = 0;
+        while data.len() - pos >= 4 {
+            let mut len_bytes = [0u8; 4];
+            len_bytes.copy_from_slice(&data[pos..pos + 4]);
+            let len = u32::from_be_bytes(len_bytes) as usize;
+            pos += 4;
+            if data.len() - pos < len {
+                break;
+            }
+            match M::decode(&data[pos..pos + len]) {
+                Ok(msg) => msgs.push(msg),
+                Err(e) => {
+                    warn!(
+                        "journal {}: decode record at {} failed: {}",
+                        self.path.display(),
+                        pos - 4,
+                        e
+                    );
+                    return Ok(msgs);
+                }
+            }
+            pos += len;
+        }
+        if pos < data.len() {
+            warn!(
+                "journal {}: ignore torn record at {}",
+                self.path.display(),
+                pos
+            );
+        }
+        Ok(msgs)
+    }
+}
+
+fn encode_record<M: Message>(msg: &M) -> Vec<u8> {
+    let len = msg.encoded_len();
+    let mut buf = Vec::with_capacity(4 + len);
+    buf.extend_from_slice(&(len as u32).to_be_bytes());
+    // encode into Vec never fails
+    msg.encode(&mut buf).unwrap();
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Journal;
+    use cita_cloud_proto::common::Hash;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("controller-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn hash(n: u8) -> Hash {
+        Hash { hash: vec![n; 32] }
+    }
+
+    #[test]
+    fn append_rewrite_test() {
+        let path = journal_path("append_rewrite");
+        let mut journal = Journal::open(&path).unwrap();
+        assert!(journal.load::<Hash>().unwrap().is_empty());
+
+        journal.append(&hash(1)).unwrap();
+        journal.append(&hash(2)).unwrap();
+        assert_eq!(journal.load::<Hash>().unwrap(), vec![hash(1), hash(2)]);
+
+        journal.rewrite(&[hash(2)]).unwrap();
+        journal.append(&hash(3)).unwrap();
+        assert_eq!(journal.load::<Hash>().unwrap(), vec![hash(2), hash(3)]);
+
+        // reopen keeps records
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.load::<Hash>().unwrap(), vec![hash(2), hash(3)]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn torn_record_test() {
+        let path = journal_path("torn_record");
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(&hash(1)).unwrap();
+
+        // crash in the middle of writing a record
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 0, 34, 10, 32, 1]).unwrap();
+        assert_eq!(journal.load::<Hash>().unwrap(), vec![hash(1)]);
+
+        journal.rewrite(&[hash(1)]).unwrap();
+        journal.append(&hash(2)).unwrap();
+        assert_eq!(journal.load::<Hash>().unwrap(), vec![hash(1), hash(2)]);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,33 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod auth;
+pub mod chain;
+pub mod config;
+pub mod controller;
+pub mod crypto;
+pub mod genesis;
+pub mod journal;
+pub mod merkle;
+pub mod node_manager;
+pub mod panic_hook;
+pub mod pool;
+pub mod protocol;
+#[macro_use]
+pub mod util;
+pub mod error;
+pub mod event;
+pub mod utxo_set;
+
+pub const DEFAULT_PACKAGE_LIMIT: usize = 6000;
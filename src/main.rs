@@ -12,23 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-mod auth;
-mod chain;
-mod config;
-mod controller;
-mod genesis;
-mod node_manager;
-mod panic_hook;
-mod pool;
-mod protocol;
-#[macro_use]
-mod util;
-mod error;
-mod event;
-mod utxo_set;
-
-use crate::panic_hook::set_panic_handler;
 use clap::Clap;
+use controller::panic_hook::set_panic_handler;
 use git_version::git_version;
 use log::{debug, info, warn};
 
@@ -38,8 +23,6 @@ const GIT_VERSION: &str = git_version!(
 );
 const GIT_HOMEPAGE: &str = "https://github.com/cita-cloud/controller";
 
-const DEFAULT_PACKAGE_LIMIT: usize = 6000;
-
 /// This doc string acts as a help message when the user runs '--help'
 /// as do all doc strings on fields
 #[derive(Clap)]
@@ -161,7 +144,7 @@ impl RpcService for RPCServer {
             .rpc_send_raw_transaction(raw_tx, true)
             .await
             .map_or_else(
-                |e| Err(Status::invalid_argument(e.to_string())),
+                |e| Err(reject_status(e)),
                 |tx_hash| {
                     let reply = Response::new(Hash { hash: tx_hash });
                     Ok(reply)
@@ -444,7 +427,7 @@ use cita_cloud_proto::network::{
     network_msg_handler_service_server::NetworkMsgHandlerService,
     network_msg_handler_service_server::NetworkMsgHandlerServiceServer, NetworkMsg,
 };
-use util::network_client;
+use controller::util::network_client;
 
 // grpc server of network msg handler
 pub struct ControllerNetworkMsgHandlerServer {
@@ -480,23 +463,282 @@ impl NetworkMsgHandlerService for ControllerNetworkMsgHandlerServer {
     }
 }
 
-use crate::chain::ChainStep;
-use crate::config::ControllerConfig;
-use crate::controller::Controller;
-use crate::error::Error;
-use crate::event::EventTask;
-use crate::node_manager::chain_status_respond::Respond;
-use crate::node_manager::ChainStatusRespond;
-use crate::util::{
-    clean_0x, get_block_hash, get_compact_block, init_grpc_client, load_data,
+use controller::protocol::controller_ext::{
+    controller_ext_service_server::ControllerExtService,
+    controller_ext_service_server::ControllerExtServiceServer, emergency_brake, transaction_status,
+    Any, BlockHeight, EmergencyBrake, ErrorInfo, HistoricalSystemConfig, PendingTx, PendingTxs,
+    PoolStatus, PoolStatusRequest, RpcStatus, Sender, SystemConfigExt, SystemConfigRequest,
+    TransactionStatus, TxHash, TxProof,
+};
+
+const ERROR_DOMAIN: &str = "controller.cita-cloud";
+const ERROR_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.ErrorInfo";
+
+// status of rejected tx, with the machine readable reason in details as google.rpc.ErrorInfo
+fn reject_status(e: Error) -> Status {
+    let code = e.reject_code();
+    let message = e.to_string();
+    let reason = match e.reject_reason() {
+        Some(reason) => reason,
+        None => return Status::new(code, message),
+    };
+
+    let error_info = ErrorInfo {
+        reason: reason.to_owned(),
+        domain: ERROR_DOMAIN.to_owned(),
+    };
+    let rpc_status = RpcStatus {
+        code: code as i32,
+        message: message.clone(),
+        details: vec![Any {
+            type_url: ERROR_INFO_TYPE_URL.to_owned(),
+            value: error_info.encode_to_vec(),
+        }],
+    };
+    Status::with_details(code, message, rpc_status.encode_to_vec().into())
+}
+
+// grpc server of controller only rpc
+pub struct ControllerExtServer {
+    controller: Controller,
+}
+
+impl ControllerExtServer {
+    fn new(controller: Controller) -> Self {
+        ControllerExtServer { controller }
+    }
+}
+
+fn pending_tx(tx_hash: Vec<u8>, raw_tx: RawTransaction) -> PendingTx {
+    let mut raw_tx_bytes = Vec::with_capacity(raw_tx.encoded_len());
+    // encode into Vec never fails
+    raw_tx.encode(&mut raw_tx_bytes).unwrap();
+    PendingTx {
+        tx_hash,
+        raw_tx: raw_tx_bytes,
+    }
+}
+
+#[tonic::async_trait]
+impl ControllerExtService for ControllerExtServer {
+    async fn get_pool_status(
+        &self,
+        request: Request<PoolStatusRequest>,
+    ) -> Result<Response<PoolStatus>, Status> {
+        debug!("get_pool_status request: {:?}", request);
+
+        let limit = request.into_inner().limit as usize;
+        let (pending_count, tx_hashes, expired_count) =
+            self.controller.rpc_get_pool_status(limit).await;
+        Ok(Response::new(PoolStatus {
+            pending_count: pending_count as u64,
+            tx_hashes,
+            expired_count,
+        }))
+    }
+
+    async fn get_pending_tx(
+        &self,
+        request: Request<TxHash>,
+    ) -> Result<Response<PendingTx>, Status> {
+        debug!("get_pending_tx request: {:?}", request);
+
+        let tx_hash = request.into_inner().hash;
+        self.controller
+            .rpc_get_pending_tx(&tx_hash)
+            .await
+            .map_or_else(
+                |e| Err(Status::not_found(e.to_string())),
+                |raw_tx| Ok(Response::new(pending_tx(tx_hash, raw_tx))),
+            )
+    }
+
+    async fn get_pending_txs_by_sender(
+        &self,
+        request: Request<Sender>,
+    ) -> Result<Response<PendingTxs>, Status> {
+        debug!("get_pending_txs_by_sender request: {:?}", request);
+
+        let sender = request.into_inner().address;
+        let txs = self
+            .controller
+            .rpc_get_pending_txs_by_sender(&sender)
+            .await
+            .into_iter()
+            .map(|(tx_hash, raw_tx)| pending_tx(tx_hash, raw_tx))
+            .collect();
+        Ok(Response::new(PendingTxs { txs }))
+    }
+
+    async fn get_transaction_status(
+        &self,
+        request: Request<TxHash>,
+    ) -> Result<Response<TransactionStatus>, Status> {
+        debug!("get_transaction_status request: {:?}", request);
+
+        let tx_hash = request.into_inner().hash;
+        let (status, block_number, tx_index) =
+            match self.controller.rpc_get_tx_status(&tx_hash).await {
+                TxStatus::Unknown => (transaction_status::Status::Unknown, 0, 0),
+                TxStatus::Pending => (transaction_status::Status::Pending, 0, 0),
+                TxStatus::Unfinalized(height) => {
+                    (transaction_status::Status::Unfinalized, height, 0)
+                }
+                TxStatus::Finalized(height, index) => {
+                    (transaction_status::Status::Finalized, height, index)
+                }
+                TxStatus::Expired => (transaction_status::Status::Expired, 0, 0),
+            };
+        Ok(Response::new(TransactionStatus {
+            status: status as i32,
+            block_number,
+            tx_index,
+        }))
+    }
+
+    async fn get_system_config_ext(
+        &self,
+        request: Request<SystemConfigRequest>,
+    ) -> Result<Response<SystemConfigExt>, Status> {
+        debug!("get_system_config_ext request: {:?}", request);
+
+        self.controller.rpc_get_system_config().await.map_or_else(
+            |e| Err(Status::invalid_argument(e)),
+            |sys_config| {
+                let reply = Response::new(SystemConfigExt {
+                    block_limit: sys_config.block_limit,
+                    block_limit_pre_hash: sys_config
+                        .utxo_tx_hashes
+                        .get(&LOCK_ID_BLOCK_LIMIT)
+                        .unwrap()
+                        .to_owned(),
+                    emergency_brake: Some(emergency_brake(&sys_config.brake_mode)),
+                    brake_mode_pre_hash: sys_config
+                        .utxo_tx_hashes
+                        .get(&LOCK_ID_BRAKE_MODE)
+                        .unwrap()
+                        .to_owned(),
+                });
+                Ok(reply)
+            },
+        )
+    }
+
+    async fn get_system_config_at(
+        &self,
+        request: Request<BlockHeight>,
+    ) -> Result<Response<HistoricalSystemConfig>, Status> {
+        debug!("get_system_config_at request: {:?}", request);
+
+        let height = request.into_inner().height;
+        self.controller
+            .rpc_get_system_config_at(height)
+            .await
+            .map_or_else(
+                |e| match e {
+                    Error::NoBlock(_) => Err(Status::not_found(e.to_string())),
+                    _ => Err(Status::internal(e.to_string())),
+                },
+                |sys_config| {
+                    let reply = Response::new(HistoricalSystemConfig {
+                        height,
+                        version: sys_config.version,
+                        chain_id: sys_config.chain_id,
+                        admin: sys_config.admin,
+                        block_interval: sys_config.block_interval,
+                        validators: sys_config.validators,
+                        emergency_brake: sys_config.emergency_brake,
+                        admins: sys_config.admins,
+                        admin_threshold: sys_config.admin_threshold,
+                        sender_allowlist: sorted_addresses(&sys_config.sender_allowlist),
+                        sender_denylist: sorted_addresses(&sys_config.sender_denylist),
+                        block_limit: sys_config.block_limit,
+                        brake: Some(emergency_brake(&sys_config.brake_mode)),
+                    });
+                    Ok(reply)
+                },
+            )
+    }
+
+    async fn get_tx_proof(&self, request: Request<TxHash>) -> Result<Response<TxProof>, Status> {
+        debug!("get_tx_proof request: {:?}", request);
+
+        let tx_hash = request.into_inner().hash;
+        self.controller
+            .rpc_get_tx_proof(&tx_hash)
+            .await
+            .map_or_else(
+                |e| match e {
+                    Error::NoTxHeight | Error::NoTxIndex => Err(Status::not_found(e.to_string())),
+                    Error::NoMerkleRoot(_) => Err(Status::failed_precondition(e.to_string())),
+                    _ => Err(Status::internal(e.to_string())),
+                },
+                |(block_number, transactions_root, proof)| {
+                    Ok(Response::new(TxProof {
+                        block_number,
+                        transactions_root,
+                        tx_index: proof.tx_index,
+                        tx_count: proof.tx_count,
+                        siblings: proof.siblings,
+                    }))
+                },
+            )
+    }
+}
+
+fn emergency_brake(mode: &BrakeMode) -> EmergencyBrake {
+    let (mode, senders) = match mode {
+        BrakeMode::Off => (emergency_brake::Mode::Off, vec![]),
+        BrakeMode::All => (emergency_brake::Mode::All, vec![]),
+        BrakeMode::ContractCreation => (emergency_brake::Mode::ContractCreation, vec![]),
+        BrakeMode::Senders(senders) => (emergency_brake::Mode::Senders, sorted_addresses(senders)),
+    };
+    EmergencyBrake {
+        mode: mode as i32,
+        senders,
+    }
+}
+
+// the stored genesis block must commit to local init sys_config if commit_sys_config is set
+async fn check_genesis(genesis: &GenesisBlock) {
+    let (compact_block, _) = get_compact_block(0)
+        .await
+        .unwrap_or_else(|e| panic!("Error while loading genesis block: [{}]", e));
+    let transactions_root = compact_block.header.unwrap().transactions_root;
+    if !genesis.commit_sys_config {
+        if transactions_root != vec![0u8; 32] {
+            panic!(
+                "genesis block commits to init sys_config, set commit_sys_config in genesis.toml"
+            );
+        }
+    } else if transactions_root != genesis.sys_config_hash {
+        panic!(
+            "init_sys_config.toml mismatches genesis block: expect 0x{}, got 0x{}",
+            hex::encode(&transactions_root),
+            hex::encode(&genesis.sys_config_hash)
+        );
+    }
+}
+
+use controller::chain::{
+    backfill_utxo_history, recover_finalization, restore_system_config, ChainStep,
+};
+use controller::config::ControllerConfig;
+use controller::controller::{Controller, TxStatus};
+use controller::error::Error;
+use controller::event::EventTask;
+use controller::genesis::GenesisBlock;
+use controller::node_manager::chain_status_respond::Respond;
+use controller::node_manager::ChainStatusRespond;
+use controller::util::{
+    clean_0x, get_block_hash, get_compact_block, init_crypto, init_grpc_client, load_data,
     load_data_maybe_empty, reconfigure,
 };
-use crate::utxo_set::{
-    SystemConfigFile, LOCK_ID_ADMIN, LOCK_ID_BLOCK_INTERVAL, LOCK_ID_BUTTON, LOCK_ID_CHAIN_ID,
-    LOCK_ID_EMERGENCY_BRAKE, LOCK_ID_VALIDATORS, LOCK_ID_VERSION,
+use controller::utxo_set::{
+    sorted_addresses, BrakeMode, SystemConfigFile, LOCK_ID_ADMIN, LOCK_ID_BLOCK_INTERVAL,
+    LOCK_ID_BLOCK_LIMIT, LOCK_ID_BRAKE_MODE, LOCK_ID_CHAIN_ID, LOCK_ID_EMERGENCY_BRAKE,
+    LOCK_ID_VALIDATORS, LOCK_ID_VERSION,
 };
-use cita_cloud_proto::blockchain::raw_transaction::Tx::UtxoTx;
-use genesis::GenesisBlock;
 use prost::Message;
 use std::fs;
 use std::time::Duration;
@@ -510,6 +752,7 @@ async fn run(opts: RunOpts) -> Result<(), Box<dyn std::error::Error + Send + Syn
         .unwrap_or_else(|err| panic!("Error while loading config: [{}]", err));
     let config = ControllerConfig::new(&buffer);
 
+    init_crypto(config.crypto_suite);
     init_grpc_client(
         config.consensus_port,
         config.storage_port,
@@ -517,8 +760,6 @@ async fn run(opts: RunOpts) -> Result<(), Box<dyn std::error::Error + Send + Syn
         config.network_port,
     );
 
-    let block_delay_number = config.block_delay_number;
-
     let grpc_port_clone = opts.grpc_port.clone();
     let mut interval = time::interval(Duration::from_secs(3));
     loop {
@@ -550,13 +791,37 @@ async fn run(opts: RunOpts) -> Result<(), Box<dyn std::error::Error + Send + Syn
         .unwrap_or_else(|err| panic!("Error while parsing node_address: [{}]", err));
     info!("node_address: {:?}", buffer);
 
+    // load initial sys_config
+    let buffer = fs::read_to_string("init_sys_config.toml")
+        .unwrap_or_else(|err| panic!("Error while loading init_sys_config.toml: [{}]", err));
+    let init_sys_config = SystemConfigFile::new(&buffer).to_system_config();
+
     // load genesis.toml
     let buffer = fs::read_to_string("genesis.toml")
         .unwrap_or_else(|err| panic!("Error while loading genesis.toml: [{}]", err));
-    let genesis = GenesisBlock::new(&buffer);
+    let genesis = GenesisBlock::new(&buffer, &init_sys_config);
+
+    // complete the block finalization interrupted by last exit
+    let mut interval = time::interval(Duration::from_secs(3));
+    loop {
+        interval.tick().await;
+        match recover_finalization().await {
+            Ok(()) => break,
+            // retrying can't tell whether the executor has the block, check it by hand
+            Err(e @ Error::ReexecuteError(_)) => panic!("recover finalization failed: {}", e),
+            Err(e) => warn!("recover finalization failed: {}. Retrying", e),
+        }
+    }
+    loop {
+        interval.tick().await;
+        match backfill_utxo_history().await {
+            Ok(()) => break,
+            Err(e) => warn!("backfill sys_config history failed: {}. Retrying", e),
+        }
+    }
+
     let current_block_number;
     let current_block_hash;
-    let mut interval = time::interval(Duration::from_secs(3));
     loop {
         interval.tick().await;
         {
@@ -574,6 +839,7 @@ async fn run(opts: RunOpts) -> Result<(), Box<dyn std::error::Error + Send + Syn
                         current_block_number = u64::from_be_bytes(bytes);
                         current_block_hash =
                             load_data(0, 1u64.to_be_bytes().to_vec()).await.unwrap();
+                        check_genesis(&genesis).await;
                     }
                     break;
                 }
@@ -585,30 +851,13 @@ async fn run(opts: RunOpts) -> Result<(), Box<dyn std::error::Error + Send + Syn
     info!("current block number: {}", current_block_number);
     info!("current block hash: 0x{}", hex::encode(&current_block_hash));
 
-    // load initial sys_config
-    let buffer = fs::read_to_string("init_sys_config.toml")
-        .unwrap_or_else(|err| panic!("Error while loading init_sys_config.toml: [{}]", err));
-
-    let mut sys_config = SystemConfigFile::new(&buffer).to_system_config();
-    if current_block_number != 0 {
-        for id in LOCK_ID_VERSION..LOCK_ID_BUTTON {
-            let key = id.to_be_bytes().to_vec();
-            // region 0 global
-            let tx_hash = load_data_maybe_empty(0, key).await.unwrap();
-            if tx_hash.is_empty() {
-                continue;
-            }
-            // region 1: tx_hash - tx
-            let raw_tx_bytes = load_data(1, tx_hash).await.unwrap();
-            let raw_tx = RawTransaction::decode(raw_tx_bytes.as_slice()).unwrap();
-            let tx = raw_tx.tx.unwrap();
-            if let UtxoTx(utxo_tx) = tx {
-                sys_config.update(&utxo_tx, true);
-            } else {
-                panic!("tx is not utxo_tx");
-            }
-        }
-    }
+    let sys_config = if current_block_number != 0 {
+        restore_system_config(init_sys_config.clone())
+            .await
+            .unwrap()
+    } else {
+        init_sys_config.clone()
+    };
     info!("sys_config: {:?}", sys_config);
 
     // send configuration to consensus
@@ -637,10 +886,11 @@ async fn run(opts: RunOpts) -> Result<(), Box<dyn std::error::Error + Send + Syn
     let (task_sender, mut task_receiver) = mpsc::unbounded_channel();
 
     let controller = Controller::new(
-        block_delay_number,
+        &config,
         current_block_number,
         current_block_hash,
         sys_config.clone(),
+        init_sys_config,
         genesis,
         key_id,
         node_address,
@@ -830,8 +1080,11 @@ async fn run(opts: RunOpts) -> Result<(), Box<dyn std::error::Error + Send + Syn
             Consensus2ControllerServer::new(controller.clone()),
         ))
         .add_service(NetworkMsgHandlerServiceServer::new(
-            ControllerNetworkMsgHandlerServer::new(controller),
+            ControllerNetworkMsgHandlerServer::new(controller.clone()),
         ))
+        .add_service(ControllerExtServiceServer::new(ControllerExtServer::new(
+            controller,
+        )))
         .serve(addr)
         .await?;
 
@@ -0,0 +1,191 @@
+// Copyright Rivtower Technologies LLC.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::util::hash_data;
+
+/// Blocks from this version commit to a merkle root of tx hashes as transactions_root,
+/// former ones to the hash of concatenated tx hashes.
+pub const BLOCK_VERSION_MERKLE: u32 = 1;
+
+// prefixes keep a leaf from being taken as an inner node
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// Proof of a tx hash included in transactions_root of a block
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleProof {
+    pub tx_index: u64,
+    pub tx_count: u64,
+    // from leaf to root, a node without sibling is carried up and has no entry
+    pub siblings: Vec<Vec<u8>>,
+}
+
+pub fn transactions_root(version: u32, tx_hashes: &[Vec<u8>]) -> Vec<u8> {
+    if version >= BLOCK_VERSION_MERKLE {
+        merkle_root(tx_hashes)
+    } else {
+        hash_data(&tx_hashes.concat())
+    }
+}
+
+pub fn merkle_root(tx_hashes: &[Vec<u8>]) -> Vec<u8> {
+    if tx_hashes.is_empty() {
+        return hash_data(&[]);
+    }
+    let mut level = leaves(tx_hashes);
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.pop().unwrap()
+}
+
+pub fn merkle_proof(tx_hashes: &[Vec<u8>], tx_index: usize) -> Option<MerkleProof> {
+    if tx_index >= tx_hashes.len() {
+        return None;
+    }
+    let mut level = leaves(tx_hashes);
+    let mut index = tx_index;
+    let mut siblings = Vec::new();
+    while level.len() > 1 {
+        if let Some(sibling) = level.get(index ^ 1) {
+            siblings.push(sibling.clone());
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    Some(MerkleProof {
+        tx_index: tx_index as u64,
+        tx_count: tx_hashes.len() as u64,
+        siblings,
+    })
+}
+
+/// Check tx_hash is included in transactions_root of a merkle versioned block,
+/// needs nothing but the proof.
+pub fn verify_merkle_proof(transactions_root: &[u8], tx_hash: &[u8], proof: &MerkleProof) -> bool {
+    if proof.tx_index >= proof.tx_count {
+        return false;
+    }
+    let mut hash = leaf_hash(tx_hash);
+    let mut index = proof.tx_index;
+    let mut count = proof.tx_count;
+    let mut siblings = proof.siblings.iter();
+    while count > 1 {
+        if index ^ 1 < count {
+            let sibling = match siblings.next() {
+                Some(sibling) => sibling,
+                None => return false,
+            };
+            hash = if index & 1 == 0 {
+                node_hash(&hash, sibling)
+            } else {
+                node_hash(sibling, &hash)
+            };
+        }
+        index /= 2;
+        count = count / 2 + (count & 1);
+    }
+    siblings.next().is_none() && hash == transactions_root
+}
+
+fn leaves(tx_hashes: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    tx_hashes.iter().map(|tx_hash| leaf_hash(tx_hash)).collect()
+}
+
+fn next_level(level: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            _ => pair[0].clone(),
+        })
+        .collect()
+}
+
+fn leaf_hash(tx_hash: &[u8]) -> Vec<u8> {
+    hash_data(&[&[LEAF_PREFIX], tx_hash].concat())
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    hash_data(&[&[NODE_PREFIX], left, right].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        leaf_hash, merkle_proof, merkle_root, node_hash, transactions_root, verify_merkle_proof,
+        BLOCK_VERSION_MERKLE,
+    };
+    use crate::util::{hash_data, init_test_crypto};
+
+    fn tx_hashes(count: u8) -> Vec<Vec<u8>> {
+        (0..count).map(|i| vec![i; 32]).collect()
+    }
+
+    #[test]
+    fn root_test() {
+        init_test_crypto();
+        let hashes = tx_hashes(3);
+        let (a, b, c) = (
+            leaf_hash(&hashes[0]),
+            leaf_hash(&hashes[1]),
+            leaf_hash(&hashes[2]),
+        );
+        // the odd one is carried up
+        assert_eq!(merkle_root(&hashes), node_hash(&node_hash(&a, &b), &c));
+        assert_eq!(merkle_root(&hashes[..1]), a);
+        assert_eq!(merkle_root(&[]), hash_data(&[]));
+
+        assert_eq!(transactions_root(0, &hashes), hash_data(&hashes.concat()));
+        assert_eq!(
+            transactions_root(BLOCK_VERSION_MERKLE, &hashes),
+            merkle_root(&hashes)
+        );
+    }
+
+    #[test]
+    fn proof_test() {
+        init_test_crypto();
+        for count in 1..=9 {
+            let hashes = tx_hashes(count);
+            let root = merkle_root(&hashes);
+            for (index, tx_hash) in hashes.iter().enumerate() {
+                let proof = merkle_proof(&hashes, index).unwrap();
+                assert!(verify_merkle_proof(&root, tx_hash, &proof));
+
+                // another tx at the same position
+                assert!(!verify_merkle_proof(&root, &[0xff; 32], &proof));
+
+                let mut bad = proof.clone();
+                bad.tx_index = (proof.tx_index + 1) % proof.tx_count;
+                assert!(count == 1 || !verify_merkle_proof(&root, tx_hash, &bad));
+
+                let mut bad = proof.clone();
+                bad.tx_count += 1;
+                bad.siblings.push(vec![0; 32]);
+                assert!(!verify_merkle_proof(&root, tx_hash, &bad));
+
+                if let Some(sibling) = proof.siblings.first() {
+                    let mut bad = proof.clone();
+                    bad.siblings[0] = sibling.iter().map(|b| !b).collect();
+                    assert!(!verify_merkle_proof(&root, tx_hash, &bad));
+                    let mut bad = proof.clone();
+                    bad.siblings.pop();
+                    assert!(!verify_merkle_proof(&root, tx_hash, &bad));
+                }
+            }
+            assert!(merkle_proof(&hashes, count as usize).is_none());
+        }
+    }
+}
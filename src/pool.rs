@@ -12,26 +12,218 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::auth::BLOCKLIMIT;
+use crate::config::{ControllerConfig, EvictionPolicy, PackagePolicy};
+use crate::error::Error;
+use crate::journal::Journal;
+use crate::util::clean_0x;
+use crate::utxo_set::DEFAULT_BLOCK_LIMIT;
 use cita_cloud_proto::blockchain::raw_transaction::Tx;
-use cita_cloud_proto::blockchain::RawTransaction;
-use std::collections::{BTreeMap, HashMap, HashSet};
-use std::iter::FromIterator;
+use cita_cloud_proto::blockchain::{RawTransaction, RawTransactions};
+use log::{info, warn};
+use prost::Message;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+// how many dropped expired txs are remembered for status query
+const EXPIRED_RECORD_LIMIT: usize = 100_000;
+
+// journal is rewritten once removed txs in it outnumber both this and txs in pool,
+// so rewriting costs O(1) per removed tx
+const JOURNAL_COMPACT_MIN: usize = 4096;
+
+/// Decide the packaging order of pending txs.
+/// Txs with smaller rank are packaged first, txs with the same rank keep arrival order.
+pub trait OrderPolicy: Send + Sync {
+    fn rank(&self, raw_tx: &RawTransaction) -> u64;
+}
+
+pub struct FifoPolicy;
+
+impl OrderPolicy for FifoPolicy {
+    fn rank(&self, _raw_tx: &RawTransaction) -> u64 {
+        0
+    }
+}
+
+pub struct PriorityPolicy {
+    // sender address - priority
+    tags: HashMap<Vec<u8>, u32>,
+}
+
+impl PriorityPolicy {
+    pub fn new(tags: HashMap<Vec<u8>, u32>) -> Self {
+        PriorityPolicy { tags }
+    }
+}
+
+impl OrderPolicy for PriorityPolicy {
+    fn rank(&self, raw_tx: &RawTransaction) -> u64 {
+        let priority = tx_sender(raw_tx)
+            .and_then(|sender| self.tags.get(sender))
+            .cloned()
+            .unwrap_or(0);
+        (u32::MAX - priority) as u64
+    }
+}
+
+pub struct UtxoFirstPolicy;
+
+impl OrderPolicy for UtxoFirstPolicy {
+    fn rank(&self, raw_tx: &RawTransaction) -> u64 {
+        match raw_tx.tx {
+            Some(Tx::UtxoTx(_)) => 0,
+            _ => 1,
+        }
+    }
+}
+
+pub fn order_policy(config: &ControllerConfig) -> Box<dyn OrderPolicy> {
+    match config.package_policy {
+        PackagePolicy::Fifo => Box::new(FifoPolicy),
+        PackagePolicy::Priority => {
+            let mut tags = HashMap::new();
+            for (sender, priority) in config.priority_tags.iter() {
+                let sender =
+                    hex::decode(clean_0x(sender)).expect("parsing priority_tags sender failed!");
+                tags.insert(sender, *priority);
+            }
+            Box::new(PriorityPolicy::new(tags))
+        }
+        PackagePolicy::UtxoFirst => Box::new(UtxoFirstPolicy),
+    }
+}
+
+/// Capacity of the pool, 0 means no limit.
+/// Sender quota only counts normal txs, utxo txs are never evicted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoolLimit {
+    pub tx_limit: usize,
+    pub bytes_limit: usize,
+    pub sender_quota: usize,
+    pub eviction: EvictionPolicy,
+}
+
+impl PoolLimit {
+    pub fn new(config: &ControllerConfig) -> Self {
+        PoolLimit {
+            tx_limit: config.pool_tx_limit,
+            bytes_limit: config.pool_bytes_limit,
+            sender_quota: config.pool_sender_quota,
+            eviction: config.pool_eviction,
+        }
+    }
+}
+
+/// Limits of txs packaged into one block, 0 means no limit.
+/// Bytes is the encoded size of block body, quota only counts normal txs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PackageLimit {
+    pub tx_limit: usize,
+    pub bytes_limit: usize,
+    pub quota_limit: u64,
+}
+
+impl PackageLimit {
+    pub fn new(config: &ControllerConfig) -> Self {
+        PackageLimit {
+            tx_limit: config.package_limit,
+            bytes_limit: config.max_block_bytes,
+            quota_limit: config.max_block_quota,
+        }
+    }
+
+    fn is_full(&self, tx_count: usize) -> bool {
+        self.tx_limit != 0 && tx_count >= self.tx_limit
+    }
+
+    // None is a sum out of range, over any limit
+    fn is_fit(&self, bytes: Option<usize>, quota: Option<u64>) -> bool {
+        (self.bytes_limit == 0 || matches!(bytes, Some(bytes) if bytes <= self.bytes_limit))
+            && (self.quota_limit == 0 || matches!(quota, Some(quota) if quota <= self.quota_limit))
+    }
+
+    // check block body of remote proposal
+    pub fn check(&self, raw_txs: &RawTransactions) -> Result<(), Error> {
+        let tx_count = raw_txs.body.len();
+        if self.tx_limit != 0 && tx_count > self.tx_limit {
+            return Err(Error::ProposalTooManyTxs(tx_count, self.tx_limit));
+        }
+        let bytes = raw_txs.encoded_len();
+        if self.bytes_limit != 0 && bytes > self.bytes_limit {
+            return Err(Error::ProposalTooLarge(bytes, self.bytes_limit));
+        }
+        // quota of txs is set by senders, the sum may overflow
+        let quota = raw_txs
+            .body
+            .iter()
+            .try_fold(0u64, |sum, raw_tx| sum.checked_add(tx_quota(raw_tx)));
+        if !self.is_fit(Some(0), quota) {
+            return Err(Error::ProposalQuotaExceeded(
+                quota.unwrap_or(u64::MAX),
+                self.quota_limit,
+            ));
+        }
+        Ok(())
+    }
+}
+
+struct PoolTx {
+    raw_tx: RawTransaction,
+    // key of the tx in order_set
+    key: (u64, u64),
+}
 
 pub struct Pool {
-    package_limit: usize,
-    order_set: BTreeMap<u64, Vec<u8>>,
+    package_limit: PackageLimit,
+    // (rank, order) - tx_hash
+    order_set: BTreeMap<(u64, u64), Vec<u8>>,
     order: u64,
-    txs: HashMap<Vec<u8>, RawTransaction>,
+    // tx_hash - tx and its key in order_set, so removing a tx needn't scan order_set
+    txs: HashMap<Vec<u8>, PoolTx>,
+    policy: Box<dyn OrderPolicy>,
+    limit: PoolLimit,
+    total_bytes: usize,
+    // sender - count of normal txs in pool
+    senders: HashMap<Vec<u8>, usize>,
+    // (valid_until_block, order) - tx_hash, only normal txs expire
+    expiry_set: BTreeMap<(u64, u64), Vec<u8>>,
+    // order - tx_hash of normal txs, the oldest evicted first
+    arrivals: BTreeMap<u64, Vec<u8>>,
+    // count of txs dropped as expired since start
+    expired_count: u64,
+    // recent txs dropped as expired, the oldest forgotten first
+    expired_hashes: HashSet<Vec<u8>>,
+    expired_queue: VecDeque<Vec<u8>>,
+    // pending txs on disk, replayed by controller on restart
+    journal: Option<Journal>,
+    // count of txs removed since journal last rewritten
+    journal_stale: usize,
+    // block limit of sys_config, txs out of it are not packaged
+    block_limit: u64,
 }
 
 impl Pool {
-    pub fn new(package_limit: usize) -> Self {
+    pub fn new(
+        package_limit: PackageLimit,
+        policy: Box<dyn OrderPolicy>,
+        limit: PoolLimit,
+    ) -> Self {
         Pool {
             package_limit,
             order_set: BTreeMap::new(),
             order: 0,
             txs: HashMap::new(),
+            policy,
+            limit,
+            total_bytes: 0,
+            senders: HashMap::new(),
+            expiry_set: BTreeMap::new(),
+            arrivals: BTreeMap::new(),
+            expired_count: 0,
+            expired_hashes: HashSet::new(),
+            expired_queue: VecDeque::new(),
+            journal: None,
+            journal_stale: 0,
+            block_limit: DEFAULT_BLOCK_LIMIT,
         }
     }
 
@@ -42,82 +234,320 @@ impl Pool {
         order
     }
 
-    pub fn enqueue(&mut self, tx_hash: Vec<u8>, raw_tx: RawTransaction) -> bool {
+    pub fn enqueue(&mut self, tx_hash: Vec<u8>, raw_tx: RawTransaction) -> Result<(), Error> {
         if self.txs.contains_key(&tx_hash) {
-            false
-        } else {
-            let order = self.get_order();
-            self.order_set.insert(order, tx_hash.clone());
-            self.txs.insert(tx_hash, raw_tx);
-            true
+            return Err(Error::DupTransaction(tx_hash));
+        }
+
+        if let Some(sender) = normal_tx_sender(&raw_tx) {
+            if self.limit.sender_quota != 0
+                && self.senders.get(sender).cloned().unwrap_or(0) >= self.limit.sender_quota
+            {
+                return Err(Error::SenderQuotaExceeded(sender.clone()));
+            }
+        }
+
+        let size = raw_tx.encoded_len();
+        if self.limit.bytes_limit != 0 && size > self.limit.bytes_limit {
+            return Err(Error::PoolFull);
         }
+        while self.is_full(size) {
+            match self.eviction_victim(&raw_tx) {
+                Some(hash) => {
+                    warn!("pool is full, evict tx(0x{})", hex::encode(&hash));
+                    self.remove(&hash);
+                }
+                None => return Err(Error::PoolFull),
+            }
+        }
+
+        let key = (self.policy.rank(&raw_tx), self.get_order());
+        self.total_bytes += size;
+        if let Some(sender) = normal_tx_sender(&raw_tx) {
+            *self.senders.entry(sender.clone()).or_insert(0) += 1;
+        }
+        if let Some(valid_until_block) = normal_tx_valid_until_block(&raw_tx) {
+            self.expiry_set
+                .insert((valid_until_block, key.1), tx_hash.clone());
+            self.arrivals.insert(key.1, tx_hash.clone());
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.append(&raw_tx) {
+                warn!(
+                    "append tx(0x{}) to journal failed: {}",
+                    hex::encode(&tx_hash),
+                    e
+                );
+            }
+        }
+        self.order_set.insert(key, tx_hash.clone());
+        self.txs.insert(tx_hash, PoolTx { raw_tx, key });
+        Ok(())
     }
 
-    pub fn update(&mut self, tx_hash_list: &Vec<Vec<u8>>) {
-        let tx_hash_list = HashSet::from_iter(tx_hash_list);
-        self.update_txs(&tx_hash_list);
-        self.update_order_set(&tx_hash_list);
+    fn is_full(&self, size: usize) -> bool {
+        (self.limit.tx_limit != 0 && self.txs.len() >= self.limit.tx_limit)
+            || (self.limit.bytes_limit != 0 && self.total_bytes + size > self.limit.bytes_limit)
     }
 
-    fn update_order_set(&mut self, tx_hash_list: &HashSet<&Vec<u8>>) {
-        self.order_set = self
-            .order_set
-            .clone()
-            .into_iter()
-            .filter(|(_, hash)| !tx_hash_list.contains(hash))
-            .collect();
+    // find the normal tx to drop for the new one, None means reject the new one
+    fn eviction_victim(&self, new_tx: &RawTransaction) -> Option<Vec<u8>> {
+        match self.limit.eviction {
+            EvictionPolicy::Reject => None,
+            EvictionPolicy::Oldest => self.arrivals.values().next().cloned(),
+            EvictionPolicy::SoonestExpiry => {
+                let ((valid_until_block, _), hash) = self.expiry_set.iter().next()?;
+                // utxo tx never expire
+                let new_valid_until_block = normal_tx_valid_until_block(new_tx).unwrap_or(u64::MAX);
+                if *valid_until_block < new_valid_until_block {
+                    Some(hash.clone())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, tx_hash: &[u8]) -> Option<RawTransaction> {
+        let PoolTx { raw_tx, key } = self.txs.remove(tx_hash)?;
+        self.order_set.remove(&key);
+        if let Some(valid_until_block) = normal_tx_valid_until_block(&raw_tx) {
+            self.expiry_set.remove(&(valid_until_block, key.1));
+            self.arrivals.remove(&key.1);
+        }
+        self.total_bytes -= raw_tx.encoded_len();
+        if let Some(sender) = normal_tx_sender(&raw_tx) {
+            if let Some(count) = self.senders.get_mut(sender) {
+                *count -= 1;
+                if *count == 0 {
+                    self.senders.remove(sender);
+                }
+            }
+        }
+        self.journal_stale += 1;
+        Some(raw_tx)
+    }
+
+    pub fn update(&mut self, tx_hash_list: &[Vec<u8>]) {
+        for tx_hash in tx_hash_list {
+            self.remove(tx_hash);
+        }
+        if self.journal_stale > JOURNAL_COMPACT_MIN.max(self.txs.len()) {
+            self.compact_journal();
+        }
+    }
+
+    // start journaling, the journal is rewritten with txs now in pool
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+        self.compact_journal();
+    }
+
+    fn compact_journal(&mut self) {
+        if let Some(journal) = self.journal.as_mut() {
+            let txs = &self.txs;
+            let raw_txs = self.order_set.values().map(|hash| &txs[hash].raw_tx);
+            if let Err(e) = journal.rewrite(raw_txs) {
+                warn!("rewrite pool journal failed: {}", e);
+                return;
+            }
+        }
+        self.journal_stale = 0;
     }
 
-    fn update_txs(&mut self, tx_hash_list: &HashSet<&Vec<u8>>) {
-        self.txs = self
+    // drop normal txs whose sender is no longer permitted
+    pub fn drop_unpermitted<F: Fn(&[u8]) -> bool>(&mut self, is_permitted: F) -> usize {
+        let unpermitted: Vec<Vec<u8>> = self
             .txs
-            .clone()
-            .into_iter()
-            .filter(|(hash, _)| !tx_hash_list.contains(hash))
+            .iter()
+            .filter(|(_, pool_tx)| {
+                matches!(normal_tx_sender(&pool_tx.raw_tx), Some(sender) if !is_permitted(sender))
+            })
+            .map(|(hash, _)| hash.clone())
             .collect();
+        for hash in unpermitted.iter() {
+            info!("drop tx(0x{}) of unpermitted sender", hex::encode(hash));
+            self.remove(hash);
+        }
+        unpermitted.len()
+    }
+
+    // drop txs which can never be packaged after block `height` finalized,
+    // next package height is `height + 1`, see tx_is_valid
+    pub fn sweep_expired(&mut self, height: u64) -> usize {
+        let unexpired = self.expiry_set.split_off(&(height + 2, 0));
+        let expired = std::mem::replace(&mut self.expiry_set, unexpired);
+        for ((valid_until_block, _), hash) in expired.iter() {
+            info!(
+                "drop expired tx(0x{}), valid_until_block: {}, h: {}",
+                hex::encode(hash),
+                valid_until_block,
+                height
+            );
+            self.remove(hash);
+            self.record_expired(hash.clone());
+        }
+        self.expired_count += expired.len() as u64;
+        if !expired.is_empty() {
+            info!(
+                "drop {} expired txs at h: {}, {} dropped since start",
+                expired.len(),
+                height,
+                self.expired_count
+            );
+        }
+        expired.len()
+    }
+
+    pub fn expired_count(&self) -> u64 {
+        self.expired_count
+    }
+
+    fn record_expired(&mut self, tx_hash: Vec<u8>) {
+        if self.expired_queue.len() >= EXPIRED_RECORD_LIMIT {
+            if let Some(oldest) = self.expired_queue.pop_front() {
+                self.expired_hashes.remove(&oldest);
+            }
+        }
+        self.expired_hashes.insert(tx_hash.clone());
+        self.expired_queue.push_back(tx_hash);
+    }
+
+    // whether tx was dropped as expired recently
+    pub fn is_expired(&self, tx_hash: &[u8]) -> bool {
+        self.expired_hashes.contains(tx_hash)
     }
 
     pub fn package(&mut self, height: u64) -> (Vec<Vec<u8>>, Vec<RawTransaction>) {
         let mut invalid_tx_list = Vec::new();
         let mut tx_list = Vec::new();
         let mut tx_hash_list = Vec::new();
+        let mut bytes = 0usize;
+        let mut quota = 0u64;
 
-        for (_, hash) in self.order_set.iter() {
-            match self.txs.get(hash) {
-                Some(raw_tx) => {
-                    if tx_is_valid(raw_tx, height) {
-                        tx_list.push(raw_tx.clone());
-                        tx_hash_list.push(hash.clone());
-                    } else {
-                        invalid_tx_list.push(hash.clone());
-                    }
-                    if tx_hash_list.len() >= self.package_limit {
-                        break;
-                    }
-                }
-                None => invalid_tx_list.push(hash.clone()),
+        for hash in self.order_set.values() {
+            let raw_tx = &self.txs[hash].raw_tx;
+            if !tx_is_valid(raw_tx, height, self.block_limit) {
+                invalid_tx_list.push(hash.clone());
+                continue;
+            }
+            let tx_bytes = tx_body_len(raw_tx);
+            let tx_quota = tx_quota(raw_tx);
+            let new_bytes = bytes.checked_add(tx_bytes);
+            let new_quota = quota.checked_add(tx_quota);
+            // skip tx which not fit, a smaller one behind may fit
+            if !self.package_limit.is_fit(new_bytes, new_quota) {
+                continue;
+            }
+            // out of range only without limit
+            bytes = new_bytes.unwrap_or(usize::MAX);
+            quota = new_quota.unwrap_or(u64::MAX);
+            tx_list.push(raw_tx.clone());
+            tx_hash_list.push(hash.clone());
+            if self.package_limit.is_full(tx_hash_list.len()) {
+                break;
             }
         }
 
-        self.update(&invalid_tx_list);
+        // journal is left to update of finalized blocks
+        for hash in invalid_tx_list {
+            if let Some(raw_tx) = self.remove(&hash) {
+                // the others are beyond block limit
+                if matches!(normal_tx_valid_until_block(&raw_tx), Some(v) if v <= height) {
+                    self.record_expired(hash);
+                    self.expired_count += 1;
+                }
+            }
+        }
         (tx_hash_list, tx_list)
     }
 
+    pub fn set_block_limit(&mut self, block_limit: u64) {
+        self.block_limit = block_limit;
+    }
+
+    pub fn package_limit(&self) -> PackageLimit {
+        self.package_limit
+    }
+
     pub fn len(&self) -> usize {
         self.order_set.len()
     }
 
-    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.order_set.is_empty()
+    }
+
     pub fn is_contain(&self, tx_hash: &[u8]) -> bool {
-        self.txs.get(tx_hash).is_some()
+        self.txs.contains_key(tx_hash)
     }
 
     pub fn pool_get_tx(&self, tx_hash: &[u8]) -> Option<RawTransaction> {
-        self.txs.get(tx_hash).cloned()
+        self.txs.get(tx_hash).map(|pool_tx| pool_tx.raw_tx.clone())
+    }
+
+    // hashes of pending txs in packaging order, limit 0 means all
+    pub fn pool_get_hashes(&self, limit: usize) -> Vec<Vec<u8>> {
+        let limit = if limit == 0 { self.len() } else { limit };
+        self.order_set.values().take(limit).cloned().collect()
+    }
+
+    // pending txs of sender in packaging order
+    pub fn pool_get_txs_by_sender(&self, sender: &[u8]) -> Vec<(Vec<u8>, RawTransaction)> {
+        self.order_set
+            .values()
+            .filter_map(|hash| {
+                let raw_tx = &self.txs[hash].raw_tx;
+                if tx_sender(raw_tx).map(|s| s.as_slice()) == Some(sender) {
+                    Some((hash.clone(), raw_tx.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+fn tx_sender(raw_tx: &RawTransaction) -> Option<&Vec<u8>> {
+    match raw_tx.tx {
+        Some(Tx::NormalTx(ref normal_tx)) => normal_tx.witness.as_ref().map(|w| &w.sender),
+        Some(Tx::UtxoTx(ref utxo_tx)) => utxo_tx.witnesses.first().map(|w| &w.sender),
+        None => None,
+    }
+}
+
+fn normal_tx_sender(raw_tx: &RawTransaction) -> Option<&Vec<u8>> {
+    match raw_tx.tx {
+        Some(Tx::NormalTx(ref normal_tx)) => normal_tx.witness.as_ref().map(|w| &w.sender),
+        _ => None,
+    }
+}
+
+fn normal_tx_valid_until_block(raw_tx: &RawTransaction) -> Option<u64> {
+    match raw_tx.tx {
+        Some(Tx::NormalTx(ref normal_tx)) => normal_tx
+            .transaction
+            .as_ref()
+            .map(|tx| tx.valid_until_block),
+        _ => None,
     }
 }
 
-fn tx_is_valid(raw_tx: &RawTransaction, height: u64) -> bool {
+fn tx_quota(raw_tx: &RawTransaction) -> u64 {
+    match raw_tx.tx {
+        Some(Tx::NormalTx(ref normal_tx)) => {
+            normal_tx.transaction.as_ref().map_or(0, |tx| tx.quota)
+        }
+        _ => 0,
+    }
+}
+
+// size the tx adds to encoded block body
+fn tx_body_len(raw_tx: &RawTransaction) -> usize {
+    prost::encoding::message::encoded_len(1, raw_tx)
+}
+
+fn tx_is_valid(raw_tx: &RawTransaction, height: u64, block_limit: u64) -> bool {
     let valid_until_block = {
         match raw_tx.tx {
             Some(Tx::NormalTx(ref normal_tx)) => match normal_tx.transaction {
@@ -131,5 +561,460 @@ fn tx_is_valid(raw_tx: &RawTransaction, height: u64) -> bool {
         }
     };
 
-    height < valid_until_block && valid_until_block <= (height + BLOCKLIMIT)
+    height < valid_until_block && valid_until_block <= (height + block_limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        FifoPolicy, PackageLimit, Pool, PoolLimit, PriorityPolicy, UtxoFirstPolicy,
+        JOURNAL_COMPACT_MIN,
+    };
+    use crate::config::EvictionPolicy;
+    use crate::error::Error;
+    use crate::journal::Journal;
+    use cita_cloud_proto::blockchain::raw_transaction::Tx;
+    use cita_cloud_proto::blockchain::{
+        RawTransaction, RawTransactions, Transaction, UnverifiedTransaction,
+        UnverifiedUtxoTransaction, Witness,
+    };
+    use prost::Message;
+    use std::collections::HashMap;
+
+    fn normal_tx(hash: u8, sender: u8, valid_until_block: u64) -> (Vec<u8>, RawTransaction) {
+        let tx_hash = vec![hash; 32];
+        let raw_tx = RawTransaction {
+            tx: Some(Tx::NormalTx(UnverifiedTransaction {
+                transaction: Some(Transaction {
+                    valid_until_block,
+                    ..Default::default()
+                }),
+                transaction_hash: tx_hash.clone(),
+                witness: Some(Witness {
+                    sender: vec![sender; 20],
+                    ..Default::default()
+                }),
+            })),
+        };
+        (tx_hash, raw_tx)
+    }
+
+    fn package_limit(tx_limit: usize) -> PackageLimit {
+        PackageLimit {
+            tx_limit,
+            ..Default::default()
+        }
+    }
+
+    fn sized_tx(hash: u8, data_len: usize, quota: u64) -> (Vec<u8>, RawTransaction) {
+        let (tx_hash, mut raw_tx) = normal_tx(hash, hash, 50);
+        if let Some(Tx::NormalTx(ref mut normal_tx)) = raw_tx.tx {
+            let tx = normal_tx.transaction.as_mut().unwrap();
+            tx.data = vec![0; data_len];
+            tx.quota = quota;
+        }
+        (tx_hash, raw_tx)
+    }
+
+    fn utxo_tx(hash: u8) -> (Vec<u8>, RawTransaction) {
+        let tx_hash = vec![hash; 32];
+        let raw_tx = RawTransaction {
+            tx: Some(Tx::UtxoTx(UnverifiedUtxoTransaction {
+                transaction: Some(Default::default()),
+                transaction_hash: tx_hash.clone(),
+                witnesses: vec![],
+            })),
+        };
+        (tx_hash, raw_tx)
+    }
+
+    #[test]
+    fn fifo_test() {
+        let mut pool = Pool::new(
+            package_limit(10),
+            Box::new(FifoPolicy),
+            PoolLimit::default(),
+        );
+        for (hash, raw_tx) in [normal_tx(1, 1, 50), utxo_tx(2), normal_tx(3, 1, 50)] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        let (hashes, _) = pool.package(1);
+        assert_eq!(hashes, vec![vec![1; 32], vec![2; 32], vec![3; 32]]);
+    }
+
+    #[test]
+    fn utxo_first_test() {
+        let mut pool = Pool::new(
+            package_limit(10),
+            Box::new(UtxoFirstPolicy),
+            PoolLimit::default(),
+        );
+        for (hash, raw_tx) in [
+            normal_tx(1, 1, 50),
+            utxo_tx(2),
+            normal_tx(3, 1, 50),
+            utxo_tx(4),
+        ] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        let (hashes, _) = pool.package(1);
+        assert_eq!(
+            hashes,
+            vec![vec![2; 32], vec![4; 32], vec![1; 32], vec![3; 32]]
+        );
+    }
+
+    #[test]
+    fn priority_test() {
+        let mut tags = HashMap::new();
+        tags.insert(vec![2; 20], 1);
+        tags.insert(vec![3; 20], 5);
+        let mut pool = Pool::new(
+            package_limit(2),
+            Box::new(PriorityPolicy::new(tags)),
+            PoolLimit::default(),
+        );
+        for (hash, raw_tx) in [
+            normal_tx(1, 1, 50),
+            normal_tx(2, 2, 50),
+            normal_tx(3, 3, 50),
+        ] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        let (hashes, _) = pool.package(1);
+        assert_eq!(hashes, vec![vec![3; 32], vec![2; 32]]);
+    }
+
+    fn limited_pool(tx_limit: usize, sender_quota: usize, eviction: EvictionPolicy) -> Pool {
+        Pool::new(
+            package_limit(10),
+            Box::new(FifoPolicy),
+            PoolLimit {
+                tx_limit,
+                bytes_limit: 0,
+                sender_quota,
+                eviction,
+            },
+        )
+    }
+
+    #[test]
+    fn inspect_test() {
+        let mut pool = Pool::new(
+            package_limit(10),
+            Box::new(UtxoFirstPolicy),
+            PoolLimit::default(),
+        );
+        for (hash, raw_tx) in [
+            normal_tx(1, 1, 50),
+            normal_tx(2, 2, 50),
+            utxo_tx(3),
+            normal_tx(4, 1, 50),
+        ] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+
+        assert_eq!(
+            pool.pool_get_hashes(0),
+            vec![vec![3; 32], vec![1; 32], vec![2; 32], vec![4; 32]]
+        );
+        assert_eq!(pool.pool_get_hashes(2), vec![vec![3; 32], vec![1; 32]]);
+        let hashes: Vec<Vec<u8>> = pool
+            .pool_get_txs_by_sender(&[1; 20])
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect();
+        assert_eq!(hashes, vec![vec![1; 32], vec![4; 32]]);
+        assert!(pool.pool_get_txs_by_sender(&[9; 20]).is_empty());
+    }
+
+    #[test]
+    fn pool_full_test() {
+        let mut pool = limited_pool(2, 0, EvictionPolicy::Reject);
+        for (hash, raw_tx) in [normal_tx(1, 1, 50), normal_tx(2, 2, 50)] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        let (hash, raw_tx) = normal_tx(3, 3, 50);
+        assert!(matches!(pool.enqueue(hash, raw_tx), Err(Error::PoolFull)));
+        assert_eq!(pool.len(), 2);
+
+        pool.update(&[vec![1; 32]]);
+        let (hash, raw_tx) = normal_tx(3, 3, 50);
+        pool.enqueue(hash, raw_tx).unwrap();
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn evict_oldest_test() {
+        let mut pool = limited_pool(2, 0, EvictionPolicy::Oldest);
+        // the oldest goes first even if it expires later
+        for (hash, raw_tx) in [utxo_tx(1), normal_tx(2, 2, 80), normal_tx(3, 3, 50)] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        assert!(pool.is_contain(&[1; 32]));
+        assert!(!pool.is_contain(&[2; 32]));
+        assert!(pool.is_contain(&[3; 32]));
+
+        let (hash, raw_tx) = normal_tx(4, 4, 90);
+        pool.enqueue(hash, raw_tx).unwrap();
+        assert!(!pool.is_contain(&[3; 32]));
+        assert!(pool.is_contain(&[4; 32]));
+    }
+
+    #[test]
+    fn evict_soonest_expiry_test() {
+        let mut pool = limited_pool(2, 0, EvictionPolicy::SoonestExpiry);
+        for (hash, raw_tx) in [normal_tx(1, 1, 60), normal_tx(2, 2, 30)] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        let (hash, raw_tx) = normal_tx(3, 3, 20);
+        assert!(matches!(pool.enqueue(hash, raw_tx), Err(Error::PoolFull)));
+
+        let (hash, raw_tx) = normal_tx(3, 3, 40);
+        pool.enqueue(hash, raw_tx).unwrap();
+        assert!(pool.is_contain(&[1; 32]));
+        assert!(!pool.is_contain(&[2; 32]));
+        assert!(pool.is_contain(&[3; 32]));
+    }
+
+    #[test]
+    fn sender_quota_test() {
+        let mut pool = limited_pool(0, 1, EvictionPolicy::Reject);
+        let (hash, raw_tx) = normal_tx(1, 1, 50);
+        pool.enqueue(hash, raw_tx).unwrap();
+        let (hash, raw_tx) = normal_tx(2, 1, 50);
+        assert!(matches!(
+            pool.enqueue(hash, raw_tx),
+            Err(Error::SenderQuotaExceeded(_))
+        ));
+        let (hash, raw_tx) = normal_tx(3, 2, 50);
+        pool.enqueue(hash, raw_tx).unwrap();
+
+        pool.update(&[vec![1; 32]]);
+        let (hash, raw_tx) = normal_tx(2, 1, 50);
+        pool.enqueue(hash, raw_tx).unwrap();
+    }
+
+    #[test]
+    fn block_limit_test() {
+        let mut pool = Pool::new(
+            package_limit(10),
+            Box::new(FifoPolicy),
+            PoolLimit::default(),
+        );
+        for (hash, raw_tx) in [normal_tx(1, 1, 50), normal_tx(2, 1, 200)] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        pool.set_block_limit(300);
+        let (hashes, _) = pool.package(1);
+        assert_eq!(hashes, vec![vec![1; 32], vec![2; 32]]);
+
+        // tx beyond the new limit is dropped
+        pool.set_block_limit(100);
+        let (hashes, _) = pool.package(1);
+        assert_eq!(hashes, vec![vec![1; 32]]);
+        assert_eq!(pool.len(), 1);
+        assert!(!pool.is_expired(&[2; 32]));
+
+        // expired tx dropped by package is recorded as sweep_expired does
+        let (hashes, _) = pool.package(50);
+        assert!(hashes.is_empty());
+        assert!(pool.is_expired(&[1; 32]));
+        assert_eq!(pool.expired_count(), 1);
+    }
+
+    #[test]
+    fn sweep_expired_test() {
+        let mut pool = Pool::new(
+            package_limit(10),
+            Box::new(FifoPolicy),
+            PoolLimit::default(),
+        );
+        for (hash, raw_tx) in [
+            normal_tx(1, 1, 10),
+            utxo_tx(2),
+            normal_tx(3, 1, 11),
+            normal_tx(4, 1, 12),
+        ] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+
+        assert_eq!(pool.sweep_expired(8), 0);
+        assert_eq!(pool.sweep_expired(10), 2);
+        assert_eq!(pool.expired_count(), 2);
+        assert!(!pool.is_contain(&[1; 32]));
+        assert!(pool.is_contain(&[2; 32]));
+        assert!(!pool.is_contain(&[3; 32]));
+        assert!(pool.is_contain(&[4; 32]));
+        assert_eq!(pool.len(), 2);
+        assert!(pool.is_expired(&[1; 32]));
+        assert!(pool.is_expired(&[3; 32]));
+        assert!(!pool.is_expired(&[4; 32]));
+
+        // removed txs leave the expiry index too
+        pool.update(&[vec![4; 32]]);
+        assert_eq!(pool.sweep_expired(100), 0);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn drop_unpermitted_test() {
+        let mut pool = Pool::new(
+            package_limit(10),
+            Box::new(FifoPolicy),
+            PoolLimit::default(),
+        );
+        for (hash, raw_tx) in [normal_tx(1, 1, 50), utxo_tx(2), normal_tx(3, 2, 50)] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        assert_eq!(pool.drop_unpermitted(|sender| sender != [1; 20]), 1);
+        assert_eq!(pool.pool_get_hashes(0), vec![vec![2; 32], vec![3; 32]]);
+    }
+
+    #[test]
+    fn journal_test() {
+        let path = std::env::temp_dir().join(format!("controller-pool-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut pool = Pool::new(
+            package_limit(10),
+            Box::new(FifoPolicy),
+            PoolLimit::default(),
+        );
+        let (hash, raw_tx) = normal_tx(1, 1, 50);
+        pool.enqueue(hash, raw_tx).unwrap();
+        pool.set_journal(Journal::open(&path).unwrap());
+        for (hash, raw_tx) in [utxo_tx(2), normal_tx(3, 1, 50)] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(
+            journal.load::<RawTransaction>().unwrap(),
+            vec![normal_tx(1, 1, 50).1, utxo_tx(2).1, normal_tx(3, 1, 50).1]
+        );
+
+        // a few removed txs are left in journal
+        pool.update(&[vec![1; 32]]);
+        assert_eq!(journal.load::<RawTransaction>().unwrap().len(), 3);
+
+        let hashes: Vec<Vec<u8>> = (0..JOURNAL_COMPACT_MIN as u64)
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect();
+        for hash in hashes.iter() {
+            pool.enqueue(hash.clone(), normal_tx(0, 0, 50).1).unwrap();
+        }
+        pool.update(&hashes);
+        assert_eq!(
+            journal.load::<RawTransaction>().unwrap(),
+            vec![utxo_tx(2).1, normal_tx(3, 1, 50).1]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn package_bytes_limit_test() {
+        let (_, small) = sized_tx(0, 100, 0);
+        let small_len = RawTransactions { body: vec![small] }.encoded_len();
+        let limit = PackageLimit {
+            tx_limit: 10,
+            bytes_limit: small_len * 2,
+            quota_limit: 0,
+        };
+        let mut pool = Pool::new(limit, Box::new(FifoPolicy), PoolLimit::default());
+        for (hash, raw_tx) in [
+            sized_tx(1, 100, 0),
+            sized_tx(2, 1000, 0),
+            sized_tx(3, 100, 0),
+        ] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+
+        // the large tx is skipped and stays in pool
+        let (hashes, tx_list) = pool.package(1);
+        assert_eq!(hashes, vec![vec![1; 32], vec![3; 32]]);
+        let body = RawTransactions { body: tx_list };
+        assert_eq!(body.encoded_len(), small_len * 2);
+        assert!(limit.check(&body).is_ok());
+        assert_eq!(pool.len(), 3);
+
+        let (_, large) = sized_tx(2, 1000, 0);
+        body_check_err(limit, vec![large.clone(), large]);
+    }
+
+    #[test]
+    fn package_quota_limit_test() {
+        let limit = PackageLimit {
+            tx_limit: 10,
+            bytes_limit: 0,
+            quota_limit: 100,
+        };
+        let mut pool = Pool::new(limit, Box::new(FifoPolicy), PoolLimit::default());
+        for (hash, raw_tx) in [
+            sized_tx(1, 0, 60),
+            sized_tx(2, 0, 60),
+            utxo_tx(3),
+            sized_tx(4, 0, 40),
+        ] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+
+        let (hashes, _) = pool.package(1);
+        assert_eq!(hashes, vec![vec![1; 32], vec![3; 32], vec![4; 32]]);
+
+        body_check_err(limit, vec![sized_tx(1, 0, 60).1, sized_tx(2, 0, 60).1]);
+        let too_many = PackageLimit {
+            tx_limit: 1,
+            ..limit
+        };
+        body_check_err(too_many, vec![utxo_tx(1).1, utxo_tx(2).1]);
+    }
+
+    #[test]
+    fn package_quota_overflow_test() {
+        let limit = PackageLimit {
+            tx_limit: 10,
+            bytes_limit: 0,
+            quota_limit: 100,
+        };
+        let mut pool = Pool::new(limit, Box::new(FifoPolicy), PoolLimit::default());
+        for (hash, raw_tx) in [
+            sized_tx(1, 0, 60),
+            sized_tx(2, 0, u64::MAX),
+            sized_tx(3, 0, 40),
+        ] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        let (hashes, _) = pool.package(1);
+        assert_eq!(hashes, vec![vec![1; 32], vec![3; 32]]);
+
+        // sum wraps to 59 without check
+        let body = vec![sized_tx(1, 0, 60).1, sized_tx(2, 0, u64::MAX).1];
+        assert!(matches!(
+            limit.check(&RawTransactions { body: body.clone() }),
+            Err(Error::ProposalQuotaExceeded(u64::MAX, 100))
+        ));
+
+        // no quota limit
+        let unlimited = PackageLimit {
+            quota_limit: 0,
+            ..limit
+        };
+        assert!(unlimited.check(&RawTransactions { body }).is_ok());
+        let mut pool = Pool::new(unlimited, Box::new(FifoPolicy), PoolLimit::default());
+        for (hash, raw_tx) in [sized_tx(1, 0, u64::MAX), sized_tx(2, 0, u64::MAX)] {
+            pool.enqueue(hash, raw_tx).unwrap();
+        }
+        let (hashes, _) = pool.package(1);
+        assert_eq!(hashes.len(), 2);
+    }
+
+    fn body_check_err(limit: PackageLimit, body: Vec<RawTransaction>) {
+        match limit.check(&RawTransactions { body }) {
+            Err(Error::ProposalTooManyTxs(..))
+            | Err(Error::ProposalTooLarge(..))
+            | Err(Error::ProposalQuotaExceeded(..)) => {}
+            r => panic!("unexpected check result: {:?}", r),
+        }
+    }
 }
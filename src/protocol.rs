@@ -12,4 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-pub(crate) mod sync_manager;
+pub mod sync_manager;
+
+pub mod controller_ext {
+    tonic::include_proto!("controller_ext");
+}
+
+pub(crate) mod controller_storage {
+    tonic::include_proto!("controller_storage");
+}
@@ -28,6 +28,8 @@ use cita_cloud_proto::storage::{storage_service_client::StorageServiceClient, Co
 use log::{info, warn};
 use tonic::Request;
 
+use crate::config::CryptoSuite;
+use crate::crypto::{crypto_suite, Crypto};
 use crate::error::Error;
 use cita_cloud_proto::blockchain::raw_transaction::Tx;
 use cita_cloud_proto::blockchain::RawTransaction;
@@ -42,12 +44,33 @@ use tonic::Code;
 
 pub const ADDR_BYTES_LEN: usize = 20;
 pub const HASH_BYTES_LEN: usize = 32;
-pub const SM2_SIGNATURE_BYTES_LEN: usize = 128;
 
 pub static CONSENSUS_CLIENT: OnceCell<ConsensusServiceClient<Channel>> = OnceCell::const_new();
 pub static STORAGE_CLIENT: OnceCell<StorageServiceClient<Channel>> = OnceCell::const_new();
 pub static EXECUTOR_CLIENT: OnceCell<ExecutorServiceClient<Channel>> = OnceCell::const_new();
 pub static NETWORK_CLIENT: OnceCell<NetworkServiceClient<Channel>> = OnceCell::const_new();
+pub static CRYPTO: OnceCell<Box<dyn Crypto>> = OnceCell::const_new();
+
+// This must be called before any hash or signature is computed.
+pub fn init_crypto(suite: CryptoSuite) {
+    if CRYPTO.set(crypto_suite(suite)).is_err() {
+        panic!("crypto suite already initialized");
+    }
+}
+
+// a hash computed before init_crypto may be of another suite than the chain's
+pub fn crypto() -> &'static dyn Crypto {
+    CRYPTO
+        .get()
+        .expect("crypto suite not initialized, init_crypto must be called first")
+        .as_ref()
+}
+
+// tests share the suite of the process, sm as the default config
+#[cfg(test)]
+pub fn init_test_crypto() {
+    let _ = CRYPTO.set(crypto_suite(CryptoSuite::Sm));
+}
 
 // This must be called before access to clients.
 pub fn init_grpc_client(
@@ -133,37 +156,19 @@ pub async fn check_block(
 }
 
 pub fn verify_tx_signature(tx_hash: &[u8], signature: &[u8]) -> Result<Vec<u8>, Error> {
-    if signature.len() != SM2_SIGNATURE_BYTES_LEN {
+    let crypto = crypto();
+    if signature.len() != crypto.signature_len() {
         warn!(
             "signature len is not correct, item len: {}, correct len: {}",
             signature.len(),
-            SM2_SIGNATURE_BYTES_LEN
+            crypto.signature_len()
         );
         Err(Error::SigLenError)
     } else {
-        Ok(pk2address(&sm2_recover(signature, tx_hash)?))
+        Ok(crypto.pk2address(&crypto.recover(signature, tx_hash)?))
     }
 }
 
-fn sm2_recover(signature: &[u8], message: &[u8]) -> Result<Vec<u8>, Error> {
-    let r = &signature[0..32];
-    let s = &signature[32..64];
-    let pk = &signature[64..];
-
-    let signature =
-        efficient_sm2::Signature::new(r, s).map_err(|e| Error::ExpectError(e.to_string()))?;
-    let public_key = efficient_sm2::PublicKey::new(&pk[..32], &pk[32..]);
-
-    signature.verify(&public_key, message).map_or_else(
-        |e| Err(Error::ExpectError(e.to_string())),
-        |_| Ok(pk.to_vec()),
-    )
-}
-
-pub fn pk2address(pk: &[u8]) -> Vec<u8> {
-    hash_data(pk)[HASH_BYTES_LEN - ADDR_BYTES_LEN..].to_vec()
-}
-
 pub fn verify_tx_hash(tx_hash: &[u8], tx_bytes: &[u8]) -> Result<(), Error> {
     if tx_hash.len() != HASH_BYTES_LEN {
         warn!(
@@ -187,9 +192,8 @@ pub fn verify_tx_hash(tx_hash: &[u8], tx_bytes: &[u8]) -> Result<(), Error> {
     }
 }
 
-// todo wrap in library
 pub fn hash_data(data: &[u8]) -> Vec<u8> {
-    libsm::sm3::hash::Sm3Hash::new(data).get_hash().to_vec()
+    crypto().hash(data)
 }
 
 pub async fn store_data(
@@ -12,11 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::util::clean_0x;
-use cita_cloud_proto::blockchain::UnverifiedUtxoTransaction;
+use crate::util::{clean_0x, hash_data, ADDR_BYTES_LEN, HASH_BYTES_LEN};
+use cita_cloud_proto::blockchain::{Transaction, UnverifiedUtxoTransaction};
 use log::warn;
 use serde_derive::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SystemConfigFile {
@@ -26,6 +26,17 @@ pub struct SystemConfigFile {
     pub admin: String,
     pub block_interval: u32,
     pub validators: Vec<String>,
+    // admin committee, replaces admin if not empty
+    #[serde(default)]
+    pub admins: Vec<String>,
+    #[serde(default)]
+    pub admin_threshold: u32,
+    #[serde(default = "default_block_limit")]
+    pub block_limit: u64,
+}
+
+fn default_block_limit() -> u64 {
+    DEFAULT_BLOCK_LIMIT
 }
 
 impl SystemConfigFile {
@@ -43,13 +54,25 @@ impl SystemConfigFile {
                 hex::decode(clean_0x(&validator_str)).expect("parsing validator failed!");
             validators.push(validator)
         }
-        SystemConfig::new(
+        let mut sys_config = SystemConfig::new(
             self.version,
             chain_id,
             admin,
             self.block_interval,
             validators,
-        )
+        );
+        for admin_str in self.admins.iter() {
+            let admin = hex::decode(clean_0x(admin_str)).expect("parsing admins failed!");
+            sys_config.admins.push(admin);
+        }
+        sys_config.admin_threshold = self.admin_threshold;
+        assert!(self.block_limit > 0, "invalid block_limit!");
+        sys_config.block_limit = self.block_limit;
+        assert!(
+            admin_committee_is_valid(&sys_config.admins, sys_config.admin_threshold),
+            "invalid admins or admin_threshold!"
+        );
+        sys_config
     }
 }
 
@@ -64,8 +87,34 @@ pub struct SystemConfig {
     pub admin: Vec<u8>,
     pub block_interval: u32,
     pub validators: Vec<Vec<u8>>,
+    // rejects all normal txs if set
     pub emergency_brake: bool,
+    // which normal txs are rejected while emergency_brake is not set
+    pub brake_mode: BrakeMode,
+    // admin committee, utxo tx needs admin_threshold distinct admins to sign.
+    // empty means the single admin above.
+    pub admins: Vec<Vec<u8>>,
+    pub admin_threshold: u32,
+    // only these senders can send normal tx, empty means everyone
+    pub sender_allowlist: HashSet<Vec<u8>>,
+    // these senders can't send normal tx
+    pub sender_denylist: HashSet<Vec<u8>>,
+    // valid_until_block of tx must be in (h, h + block_limit],
+    // and tx hashes of the last block_limit blocks are kept for dup check
+    pub block_limit: u64,
     pub utxo_tx_hashes: HashMap<u64, Vec<u8>>,
+    // lock_id - change scheduled by utxo tx, at most one for each lock_id
+    pub pending_changes: BTreeMap<u64, PendingChange>,
+}
+
+/// A change applied when block of activation_height is finalized
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingChange {
+    pub activation_height: u64,
+    pub tx_hash: Vec<u8>,
+    // hash of the utxo tx of lock_id before it, in force again if the change fails
+    pub pre_tx_hash: Vec<u8>,
+    pub output: Vec<u8>,
 }
 
 pub const LOCK_ID_VERSION: u64 = 1_000;
@@ -74,7 +123,26 @@ pub const LOCK_ID_ADMIN: u64 = 1_002;
 pub const LOCK_ID_BLOCK_INTERVAL: u64 = 1_003;
 pub const LOCK_ID_VALIDATORS: u64 = 1_004;
 pub const LOCK_ID_EMERGENCY_BRAKE: u64 = 1_005;
-pub const LOCK_ID_BUTTON: u64 = 1_006;
+pub const LOCK_ID_ADMIN_COMMITTEE: u64 = 1_006;
+pub const LOCK_ID_SENDER_ALLOWLIST: u64 = 1_007;
+pub const LOCK_ID_SENDER_DENYLIST: u64 = 1_008;
+pub const LOCK_ID_BLOCK_LIMIT: u64 = 1_009;
+pub const LOCK_ID_BRAKE_MODE: u64 = 1_010;
+pub const LOCK_ID_BUTTON: u64 = 1_011;
+
+// utxo tx with lock_id | LOCK_ID_SCHEDULED_FLAG schedules the change of lock_id,
+// its output is 8 bytes big endian activation height followed by the output of the change
+pub const LOCK_ID_SCHEDULED_FLAG: u64 = 1 << 63;
+
+// region 0 key lock_id | LOCK_ID_HISTORY_FLAG holds the changes of lock_id in applied order,
+// each record is 8 bytes big endian height and hash of the utxo tx
+pub const LOCK_ID_HISTORY_FLAG: u64 = 1 << 62;
+const HISTORY_RECORD_LEN: usize = 8 + HASH_BYTES_LEN;
+
+pub const DEFAULT_BLOCK_LIMIT: u64 = 100;
+
+// version of SystemConfig::init_encode
+const INIT_ENCODING_VERSION: u32 = 1;
 
 impl SystemConfig {
     pub fn new(
@@ -96,18 +164,30 @@ impl SystemConfig {
             block_interval,
             validators,
             emergency_brake: false,
+            brake_mode: BrakeMode::Off,
+            admins: Vec::new(),
+            admin_threshold: 0,
+            sender_allowlist: HashSet::new(),
+            sender_denylist: HashSet::new(),
+            block_limit: DEFAULT_BLOCK_LIMIT,
             utxo_tx_hashes: map,
+            pending_changes: BTreeMap::new(),
         }
     }
 
     pub fn update(&mut self, tx: &UnverifiedUtxoTransaction, is_init: bool) -> bool {
         let tx_hash = tx.transaction_hash.clone();
         let utxo_tx = tx.clone().transaction.unwrap();
-        let lock_id = utxo_tx.lock_id;
+        let lock_id = utxo_tx.lock_id & !LOCK_ID_SCHEDULED_FLAG;
+        let is_scheduled = utxo_tx.lock_id & LOCK_ID_SCHEDULED_FLAG != 0;
         let pre_tx_hash = utxo_tx.pre_tx_hash;
         let data = utxo_tx.output;
 
         if !is_init {
+            if self.pending_changes.contains_key(&lock_id) {
+                warn!("lock_id {} has a pending change", lock_id);
+                return false;
+            }
             if let Some(hash) = self.utxo_tx_hashes.get(&lock_id) {
                 if hash != &pre_tx_hash {
                     return false;
@@ -117,73 +197,484 @@ impl SystemConfig {
             }
         }
 
-        let ret = match lock_id {
-            LOCK_ID_VERSION => {
-                self.version = u32_decode(data);
-                true
-            }
-            LOCK_ID_CHAIN_ID => {
-                if data.len() == 32 {
-                    self.chain_id = data;
-                    true
-                } else {
-                    warn!("Invalid chain id");
-                    false
+        let ret = if is_scheduled {
+            match decode_scheduled(&data) {
+                // on init, an activated scheduled change is applied at once
+                Some((_, output)) if is_init => self.apply_change(lock_id, output, true),
+                Some((activation_height, output)) => {
+                    // check output now, not when activated
+                    if let Err(e) = self.check_output(lock_id, &output) {
+                        warn!("Invalid output of lock_id {}: {}", lock_id, e);
+                        false
+                    } else {
+                        self.pending_changes.insert(
+                            lock_id,
+                            PendingChange {
+                                activation_height,
+                                tx_hash: tx_hash.clone(),
+                                pre_tx_hash: pre_tx_hash.clone(),
+                                output,
+                            },
+                        );
+                        true
+                    }
                 }
-            }
-            LOCK_ID_ADMIN => {
-                if data.len() == 20 {
-                    self.admin = data;
-                    true
-                } else {
-                    warn!("Invalid admin");
+                None => {
+                    warn!("Invalid scheduled change");
                     false
                 }
             }
-            LOCK_ID_BLOCK_INTERVAL => {
-                self.block_interval = u32_decode(data);
-                true
+        } else {
+            self.apply_change(lock_id, data, is_init)
+        };
+
+        if ret {
+            self.utxo_tx_hashes.insert(lock_id, tx_hash);
+        }
+
+        ret
+    }
+
+    /// Apply pending changes activated at height, return lock_id, tx hash and whether applied
+    /// of them. Output is checked when scheduled, so applying fails only if the check changed
+    /// meanwhile, then the change is dropped as if never scheduled.
+    pub fn activate(&mut self, height: u64) -> Vec<(u64, Vec<u8>, bool)> {
+        let lock_ids: Vec<u64> = self
+            .pending_changes
+            .iter()
+            .filter(|(_, change)| change.activation_height <= height)
+            .map(|(lock_id, _)| *lock_id)
+            .collect();
+
+        let mut activated = Vec::new();
+        for lock_id in lock_ids {
+            let change = self.pending_changes.remove(&lock_id).unwrap();
+            let applied = self.apply_change(lock_id, change.output, false);
+            if !applied {
+                warn!("apply scheduled change of lock_id {} failed", lock_id);
+                self.utxo_tx_hashes.insert(lock_id, change.pre_tx_hash);
             }
+            activated.push((lock_id, change.tx_hash, applied));
+        }
+        activated
+    }
+
+    /// Decode output of lock_id and check it against current config.
+    pub fn check_output(&self, lock_id: u64, data: &[u8]) -> Result<ConfigChange, String> {
+        let change = ConfigChange::decode(lock_id, data)?;
+        if let ConfigChange::Version(version) = change {
+            if version <= self.version {
+                return Err(format!("version {} not after {}", version, self.version));
+            }
+        }
+        Ok(change)
+    }
+
+    fn apply_change(&mut self, lock_id: u64, data: Vec<u8>, is_init: bool) -> bool {
+        // changes replayed on init are decoded as they were when committed
+        let change = if is_init {
+            ConfigChange::decode_legacy(lock_id, &data)
+        } else {
+            self.check_output(lock_id, &data)
+        };
+        let change = match change {
+            Ok(change) => change,
+            Err(e) => {
+                warn!("Invalid output of lock_id {}: {}", lock_id, e);
+                return false;
+            }
+        };
+
+        match change {
+            ConfigChange::Version(version) => self.version = version,
+            ConfigChange::ChainId(chain_id) => self.chain_id = chain_id,
+            ConfigChange::Admin(admin) => self.admin = admin,
+            ConfigChange::BlockInterval(block_interval) => self.block_interval = block_interval,
+            ConfigChange::Validators(validators) => self.validators = validators,
+            ConfigChange::EmergencyBrake(emergency_brake) => self.emergency_brake = emergency_brake,
+            ConfigChange::BrakeMode(brake_mode) => self.brake_mode = brake_mode,
+            ConfigChange::AdminCommittee(threshold, admins) => {
+                self.admins = admins;
+                self.admin_threshold = threshold;
+            }
+            ConfigChange::SenderAllowlist(allowlist) => {
+                self.sender_allowlist = allowlist.into_iter().collect()
+            }
+            ConfigChange::SenderDenylist(denylist) => {
+                self.sender_denylist = denylist.into_iter().collect()
+            }
+            ConfigChange::BlockLimit(block_limit) => self.block_limit = block_limit,
+        }
+        true
+    }
+
+    /// Items set by init_sys_config.toml in a frozen encoding, committed by genesis block.
+    /// It starts with INIT_ENCODING_VERSION, then version, chain_id, admin, block_interval,
+    /// validators, admin_threshold, admins and block_limit. Integers are big endian, byte
+    /// strings are prefixed by their 8 bytes len and lists by their 8 bytes count.
+    /// Never change it, a new encoding must come with a new INIT_ENCODING_VERSION.
+    pub fn init_encode(&self) -> Vec<u8> {
+        fn put_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+            data.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+            data.extend_from_slice(bytes);
+        }
+        fn put_list(data: &mut Vec<u8>, list: &[Vec<u8>]) {
+            data.extend_from_slice(&(list.len() as u64).to_be_bytes());
+            for bytes in list {
+                put_bytes(data, bytes);
+            }
+        }
+
+        let mut data = INIT_ENCODING_VERSION.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.version.to_be_bytes());
+        put_bytes(&mut data, &self.chain_id);
+        put_bytes(&mut data, &self.admin);
+        data.extend_from_slice(&self.block_interval.to_be_bytes());
+        put_list(&mut data, &self.validators);
+        data.extend_from_slice(&self.admin_threshold.to_be_bytes());
+        put_list(&mut data, &self.admins);
+        data.extend_from_slice(&self.block_limit.to_be_bytes());
+        data
+    }
+
+    pub fn init_hash(&self) -> Vec<u8> {
+        hash_data(&self.init_encode())
+    }
+
+    pub fn is_brake_permitted(&self, sender: &[u8], tx: &Transaction) -> bool {
+        !self.emergency_brake && self.brake_mode.permits(sender, tx)
+    }
+
+    pub fn is_sender_permitted(&self, sender: &[u8]) -> bool {
+        (self.sender_allowlist.is_empty() || self.sender_allowlist.contains(sender))
+            && !self.sender_denylist.contains(sender)
+    }
+
+    // admins who can sign utxo tx and how many of them must sign
+    pub fn admin_committee(&self) -> (&[Vec<u8>], u32) {
+        if self.admins.is_empty() {
+            (std::slice::from_ref(&self.admin), 1)
+        } else {
+            (&self.admins, self.admin_threshold)
+        }
+    }
+}
+
+/// Which normal txs are rejected by emergency brake
+#[derive(Debug, Clone, PartialEq)]
+pub enum BrakeMode {
+    Off,
+    All,
+    // tx with empty to
+    ContractCreation,
+    // all but txs of these senders
+    Senders(HashSet<Vec<u8>>),
+}
+
+impl BrakeMode {
+    pub fn permits(&self, sender: &[u8], tx: &Transaction) -> bool {
+        match self {
+            BrakeMode::Off => true,
+            BrakeMode::All => false,
+            BrakeMode::ContractCreation => !tx.to.is_empty(),
+            BrakeMode::Senders(senders) => senders.contains(sender),
+        }
+    }
+}
+
+/// A change of system config carried in utxo tx output, all integers are big endian.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigChange {
+    // 4 bytes, must be greater than current version
+    Version(u32),
+    // 32 bytes
+    ChainId(Vec<u8>),
+    // 20 bytes address
+    Admin(Vec<u8>),
+    // 4 bytes, not 0
+    BlockInterval(u32),
+    // concatenated addresses, not empty and no duplicates
+    Validators(Vec<Vec<u8>>),
+    // empty is off, [1] blocks all
+    EmergencyBrake(bool),
+    // 4 bytes threshold, then concatenated addresses of admins
+    AdminCommittee(u32, Vec<Vec<u8>>),
+    // concatenated addresses with no duplicates, empty clears the list
+    SenderAllowlist(Vec<Vec<u8>>),
+    SenderDenylist(Vec<Vec<u8>>),
+    // 8 bytes, not 0
+    BlockLimit(u64),
+    // empty or [0] is off, [1] blocks all, [2] blocks contract creation,
+    // [3] followed by concatenated addresses allows only these senders
+    BrakeMode(BrakeMode),
+}
+
+impl ConfigChange {
+    pub fn decode(lock_id: u64, data: &[u8]) -> Result<Self, String> {
+        let change = match lock_id {
+            LOCK_ID_VERSION => ConfigChange::Version(u32_decode(exact_len(data, 4)?)),
+            LOCK_ID_CHAIN_ID => ConfigChange::ChainId(exact_len(data, 32)?.to_vec()),
+            LOCK_ID_ADMIN => ConfigChange::Admin(exact_len(data, ADDR_BYTES_LEN)?.to_vec()),
+            LOCK_ID_BLOCK_INTERVAL => match u32_decode(exact_len(data, 4)?) {
+                0 => return Err("block interval is 0".to_owned()),
+                block_interval => ConfigChange::BlockInterval(block_interval),
+            },
             LOCK_ID_VALIDATORS => {
-                let mut validators = Vec::new();
-                if data.len() % 20 == 0 {
-                    for i in 0..(data.len() / 20) {
-                        validators.push(data[i * 20..(i + 1) * 20].to_vec())
-                    }
-                    self.validators = validators;
-                    true
-                } else {
-                    warn!("Invalid validators");
-                    false
+                let validators = decode_addresses(data)?;
+                if validators.is_empty() {
+                    return Err("empty validators".to_owned());
                 }
+                ConfigChange::Validators(validators)
             }
-            LOCK_ID_EMERGENCY_BRAKE => {
-                self.emergency_brake = !data.is_empty();
-                true
+            LOCK_ID_EMERGENCY_BRAKE => match data {
+                [] => ConfigChange::EmergencyBrake(false),
+                [1] => ConfigChange::EmergencyBrake(true),
+                _ => return Err("emergency brake is neither empty nor [1]".to_owned()),
+            },
+            LOCK_ID_ADMIN_COMMITTEE => {
+                if data.len() < 4 {
+                    return Err(format!("len {}, expect at least 4", data.len()));
+                }
+                let threshold = u32_decode(&data[..4]);
+                let admins = decode_addresses(&data[4..])?;
+                if !admin_committee_is_valid(&admins, threshold) {
+                    return Err(format!(
+                        "threshold {} of {} admins",
+                        threshold,
+                        admins.len()
+                    ));
+                }
+                ConfigChange::AdminCommittee(threshold, admins)
             }
-            _ => {
-                warn!("Invalid lock_id");
-                false
+            LOCK_ID_SENDER_ALLOWLIST => ConfigChange::SenderAllowlist(decode_addresses(data)?),
+            LOCK_ID_SENDER_DENYLIST => ConfigChange::SenderDenylist(decode_addresses(data)?),
+            LOCK_ID_BLOCK_LIMIT => match u64_decode(exact_len(data, 8)?) {
+                0 => return Err("block limit is 0".to_owned()),
+                block_limit => ConfigChange::BlockLimit(block_limit),
+            },
+            LOCK_ID_BRAKE_MODE => match data {
+                [] | [0] => ConfigChange::BrakeMode(BrakeMode::Off),
+                [1] => ConfigChange::BrakeMode(BrakeMode::All),
+                [2] => ConfigChange::BrakeMode(BrakeMode::ContractCreation),
+                [3, senders @ ..] => {
+                    let senders = decode_addresses(senders)?;
+                    if senders.is_empty() {
+                        return Err("empty senders of brake".to_owned());
+                    }
+                    ConfigChange::BrakeMode(BrakeMode::Senders(senders.into_iter().collect()))
+                }
+                _ => return Err("unknown brake mode".to_owned()),
+            },
+            _ => return Err("unknown lock_id".to_owned()),
+        };
+        Ok(change)
+    }
+
+    /// Decode output of a committed utxo tx as it was accepted before outputs were checked
+    /// strictly, so replaying the chain gives the same config as the nodes which committed it.
+    pub fn decode_legacy(lock_id: u64, data: &[u8]) -> Result<Self, String> {
+        let change = match lock_id {
+            LOCK_ID_VERSION => ConfigChange::Version(u32_decode(min_len(data, 4)?)),
+            LOCK_ID_BLOCK_INTERVAL => ConfigChange::BlockInterval(u32_decode(min_len(data, 4)?)),
+            LOCK_ID_VALIDATORS => ConfigChange::Validators(legacy_addresses(data)?),
+            LOCK_ID_EMERGENCY_BRAKE => ConfigChange::EmergencyBrake(!data.is_empty()),
+            LOCK_ID_ADMIN_COMMITTEE => {
+                let threshold = u32_decode(min_len(data, 4)?);
+                let admins = legacy_addresses(&data[4..])?;
+                if !admin_committee_is_valid(&admins, threshold) {
+                    return Err(format!(
+                        "threshold {} of {} admins",
+                        threshold,
+                        admins.len()
+                    ));
+                }
+                ConfigChange::AdminCommittee(threshold, admins)
             }
+            LOCK_ID_SENDER_ALLOWLIST => ConfigChange::SenderAllowlist(legacy_addresses(data)?),
+            LOCK_ID_SENDER_DENYLIST => ConfigChange::SenderDenylist(legacy_addresses(data)?),
+            _ => return ConfigChange::decode(lock_id, data),
         };
+        Ok(change)
+    }
+}
 
-        if ret {
-            self.utxo_tx_hashes.insert(lock_id, tx_hash);
+// for admin tooling to build utxo tx
+impl ConfigChange {
+    pub fn lock_id(&self) -> u64 {
+        match self {
+            ConfigChange::Version(_) => LOCK_ID_VERSION,
+            ConfigChange::ChainId(_) => LOCK_ID_CHAIN_ID,
+            ConfigChange::Admin(_) => LOCK_ID_ADMIN,
+            ConfigChange::BlockInterval(_) => LOCK_ID_BLOCK_INTERVAL,
+            ConfigChange::Validators(_) => LOCK_ID_VALIDATORS,
+            ConfigChange::EmergencyBrake(_) => LOCK_ID_EMERGENCY_BRAKE,
+            ConfigChange::AdminCommittee(..) => LOCK_ID_ADMIN_COMMITTEE,
+            ConfigChange::SenderAllowlist(_) => LOCK_ID_SENDER_ALLOWLIST,
+            ConfigChange::SenderDenylist(_) => LOCK_ID_SENDER_DENYLIST,
+            ConfigChange::BlockLimit(_) => LOCK_ID_BLOCK_LIMIT,
+            ConfigChange::BrakeMode(_) => LOCK_ID_BRAKE_MODE,
         }
+    }
 
-        ret
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            ConfigChange::Version(version) => version.to_be_bytes().to_vec(),
+            ConfigChange::ChainId(chain_id) => chain_id.clone(),
+            ConfigChange::Admin(admin) => admin.clone(),
+            ConfigChange::BlockInterval(block_interval) => block_interval.to_be_bytes().to_vec(),
+            ConfigChange::Validators(validators) => validators.concat(),
+            ConfigChange::EmergencyBrake(false) => vec![],
+            ConfigChange::EmergencyBrake(true) => vec![1],
+            ConfigChange::AdminCommittee(threshold, admins) => {
+                let mut data = threshold.to_be_bytes().to_vec();
+                data.extend_from_slice(&admins.concat());
+                data
+            }
+            ConfigChange::SenderAllowlist(allowlist) => allowlist.concat(),
+            ConfigChange::SenderDenylist(denylist) => denylist.concat(),
+            ConfigChange::BlockLimit(block_limit) => block_limit.to_be_bytes().to_vec(),
+            ConfigChange::BrakeMode(mode) => match mode {
+                BrakeMode::Off => vec![],
+                BrakeMode::All => vec![1],
+                BrakeMode::ContractCreation => vec![2],
+                BrakeMode::Senders(senders) => {
+                    let mut data = vec![3];
+                    for sender in sorted_addresses(senders) {
+                        data.extend_from_slice(&sender);
+                    }
+                    data
+                }
+            },
+        }
+    }
+
+    /// lock_id and output of the change scheduled at activation_height
+    pub fn encode_scheduled(&self, activation_height: u64) -> (u64, Vec<u8>) {
+        let mut data = activation_height.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.encode());
+        (self.lock_id() | LOCK_ID_SCHEDULED_FLAG, data)
+    }
+}
+
+fn exact_len(data: &[u8], len: usize) -> Result<&[u8], String> {
+    if data.len() == len {
+        Ok(data)
+    } else {
+        Err(format!("len {}, expect {}", data.len(), len))
+    }
+}
+
+fn min_len(data: &[u8], len: usize) -> Result<&[u8], String> {
+    if data.len() >= len {
+        Ok(data)
+    } else {
+        Err(format!("len {}, expect at least {}", data.len(), len))
+    }
+}
+
+pub fn sorted_addresses(addresses: &HashSet<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut addresses: Vec<Vec<u8>> = addresses.iter().cloned().collect();
+    addresses.sort();
+    addresses
+}
+
+// concatenated distinct addresses
+fn decode_addresses(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    if data.len() % ADDR_BYTES_LEN != 0 {
+        return Err(format!(
+            "len {} is not multiple of {}",
+            data.len(),
+            ADDR_BYTES_LEN
+        ));
+    }
+    let addresses: Vec<Vec<u8>> = data.chunks(ADDR_BYTES_LEN).map(|a| a.to_vec()).collect();
+    let distinct: HashSet<&Vec<u8>> = addresses.iter().collect();
+    if distinct.len() != addresses.len() {
+        return Err("duplicate addresses".to_owned());
+    }
+    Ok(addresses)
+}
+
+// concatenated addresses, duplicates allowed
+fn legacy_addresses(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    if data.len() % ADDR_BYTES_LEN != 0 {
+        return Err(format!(
+            "len {} is not multiple of {}",
+            data.len(),
+            ADDR_BYTES_LEN
+        ));
     }
+    Ok(data.chunks(ADDR_BYTES_LEN).map(|a| a.to_vec()).collect())
 }
 
-fn u32_decode(data: Vec<u8>) -> u32 {
+// activation height and output of the change
+pub fn decode_scheduled(data: &[u8]) -> Option<(u64, Vec<u8>)> {
+    if data.len() >= 8 {
+        Some((u64_decode(data), data[8..].to_vec()))
+    } else {
+        None
+    }
+}
+
+pub fn encode_history_record(height: u64, tx_hash: &[u8]) -> Vec<u8> {
+    let mut record = height.to_be_bytes().to_vec();
+    record.extend_from_slice(tx_hash);
+    record
+}
+
+// (height, tx hash) of changes in the order applied
+pub fn history_records(history: &[u8]) -> Vec<(u64, Vec<u8>)> {
+    history
+        .chunks_exact(HISTORY_RECORD_LEN)
+        .map(|record| (u64_decode(record), record[8..].to_vec()))
+        .collect()
+}
+
+// hash of the last change applied at or before height
+pub fn history_tx_hash_at(history: &[u8], height: u64) -> Option<Vec<u8>> {
+    history
+        .chunks_exact(HISTORY_RECORD_LEN)
+        .rev()
+        .find(|record| u64_decode(record) <= height)
+        .map(|record| record[8..].to_vec())
+}
+
+// empty committee with threshold 0 disables it
+fn admin_committee_is_valid(admins: &[Vec<u8>], threshold: u32) -> bool {
+    if admins.is_empty() {
+        return threshold == 0;
+    }
+    let distinct: HashSet<&Vec<u8>> = admins.iter().collect();
+    distinct.len() == admins.len()
+        && admins.iter().all(|admin| admin.len() == 20)
+        && threshold >= 1
+        && threshold as usize <= admins.len()
+}
+
+fn u32_decode(data: &[u8]) -> u32 {
     let mut bytes: [u8; 4] = [0; 4];
-    bytes[..4].clone_from_slice(&data[..4]);
+    bytes.clone_from_slice(&data[..4]);
     u32::from_be_bytes(bytes)
 }
 
+fn u64_decode(data: &[u8]) -> u64 {
+    let mut bytes: [u8; 8] = [0; 8];
+    bytes.clone_from_slice(&data[..8]);
+    u64::from_be_bytes(bytes)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::SystemConfigFile;
+    use super::{
+        decode_scheduled, encode_history_record, history_records, history_tx_hash_at, BrakeMode,
+        ConfigChange, SystemConfig, SystemConfigFile, DEFAULT_BLOCK_LIMIT, LOCK_ID_ADMIN,
+        LOCK_ID_ADMIN_COMMITTEE, LOCK_ID_BLOCK_INTERVAL, LOCK_ID_BLOCK_LIMIT, LOCK_ID_BRAKE_MODE,
+        LOCK_ID_BUTTON, LOCK_ID_CHAIN_ID, LOCK_ID_EMERGENCY_BRAKE, LOCK_ID_SCHEDULED_FLAG,
+        LOCK_ID_SENDER_ALLOWLIST, LOCK_ID_SENDER_DENYLIST, LOCK_ID_VALIDATORS, LOCK_ID_VERSION,
+    };
+    use crate::crypto::{Crypto, Eth};
+    use crate::util::init_test_crypto;
+    use cita_cloud_proto::blockchain::{UnverifiedUtxoTransaction, UtxoTransaction};
 
     #[test]
     fn basic_test() {
@@ -206,5 +697,408 @@ mod tests {
             sys_config.validators,
             vec![vec![1, 1, 1, 1], vec![2, 2, 2, 2]]
         );
+        assert_eq!(sys_config.block_limit, DEFAULT_BLOCK_LIMIT);
+
+        let config = SystemConfigFile::new(&format!("{}block_limit = 300", toml_str));
+        assert_eq!(config.to_system_config().block_limit, 300);
+    }
+
+    fn committee_tx(threshold: u32, admins: &[u8]) -> UnverifiedUtxoTransaction {
+        let mut output = threshold.to_be_bytes().to_vec();
+        for admin in admins {
+            output.extend_from_slice(&[*admin; 20]);
+        }
+        UnverifiedUtxoTransaction {
+            transaction: Some(UtxoTransaction {
+                output,
+                lock_id: LOCK_ID_ADMIN_COMMITTEE,
+                pre_tx_hash: vec![0u8; 33],
+                ..Default::default()
+            }),
+            transaction_hash: vec![1u8; 32],
+            witnesses: vec![],
+        }
+    }
+
+    #[test]
+    fn admin_committee_test() {
+        let mut sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        assert_eq!(sys_config.admin_committee(), (&[vec![9; 20]][..], 1));
+
+        // threshold must be in 1..=admins, admins must be distinct
+        for (threshold, admins) in [(0, &[1, 2][..]), (3, &[1, 2]), (1, &[1, 1]), (1, &[])] {
+            assert!(!sys_config.update(&committee_tx(threshold, admins), false));
+        }
+        let mut tx = committee_tx(1, &[1]);
+        tx.transaction.as_mut().unwrap().output.pop();
+        assert!(!sys_config.update(&tx, false));
+
+        assert!(sys_config.update(&committee_tx(2, &[1, 2, 3]), false));
+        assert_eq!(sys_config.admin_threshold, 2);
+        assert_eq!(
+            sys_config.admin_committee(),
+            (&[vec![1; 20], vec![2; 20], vec![3; 20]][..], 2)
+        );
+
+        // disable committee, back to single admin
+        let mut tx = committee_tx(0, &[]);
+        tx.transaction.as_mut().unwrap().pre_tx_hash = vec![1u8; 32];
+        assert!(sys_config.update(&tx, false));
+        assert_eq!(sys_config.admin_committee(), (&[vec![9; 20]][..], 1));
+    }
+
+    fn sender_list_tx(lock_id: u64, senders: &[u8]) -> UnverifiedUtxoTransaction {
+        UnverifiedUtxoTransaction {
+            transaction: Some(UtxoTransaction {
+                output: senders
+                    .iter()
+                    .flat_map(|sender| vec![*sender; 20])
+                    .collect(),
+                lock_id,
+                pre_tx_hash: vec![0u8; 33],
+                ..Default::default()
+            }),
+            transaction_hash: vec![lock_id as u8; 32],
+            witnesses: vec![],
+        }
+    }
+
+    #[test]
+    fn sender_list_test() {
+        let mut sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        assert!(sys_config.is_sender_permitted(&[1; 20]));
+
+        assert!(sys_config.update(&sender_list_tx(LOCK_ID_SENDER_ALLOWLIST, &[1, 2]), false));
+        assert!(sys_config.is_sender_permitted(&[1; 20]));
+        assert!(!sys_config.is_sender_permitted(&[3; 20]));
+
+        // denylist wins over allowlist
+        assert!(sys_config.update(&sender_list_tx(LOCK_ID_SENDER_DENYLIST, &[2]), false));
+        assert!(sys_config.is_sender_permitted(&[1; 20]));
+        assert!(!sys_config.is_sender_permitted(&[2; 20]));
+
+        // empty allowlist disables it
+        let mut tx = sender_list_tx(LOCK_ID_SENDER_ALLOWLIST, &[]);
+        tx.transaction.as_mut().unwrap().pre_tx_hash = vec![LOCK_ID_SENDER_ALLOWLIST as u8; 32];
+        assert!(sys_config.update(&tx, false));
+        assert!(sys_config.is_sender_permitted(&[3; 20]));
+        assert!(!sys_config.is_sender_permitted(&[2; 20]));
+
+        let mut tx = sender_list_tx(LOCK_ID_SENDER_DENYLIST, &[1]);
+        tx.transaction.as_mut().unwrap().pre_tx_hash = vec![LOCK_ID_SENDER_DENYLIST as u8; 32];
+        tx.transaction.as_mut().unwrap().output.pop();
+        assert!(!sys_config.update(&tx, false));
+    }
+
+    #[test]
+    fn block_limit_test() {
+        let mut sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        let tx = |output: Vec<u8>| UnverifiedUtxoTransaction {
+            transaction: Some(UtxoTransaction {
+                output,
+                lock_id: LOCK_ID_BLOCK_LIMIT,
+                pre_tx_hash: vec![0u8; 33],
+                ..Default::default()
+            }),
+            transaction_hash: vec![1u8; 32],
+            witnesses: vec![],
+        };
+
+        assert!(!sys_config.update(&tx(0u64.to_be_bytes().to_vec()), false));
+        assert!(!sys_config.update(&tx(vec![1; 4]), false));
+        assert_eq!(sys_config.block_limit, DEFAULT_BLOCK_LIMIT);
+
+        assert!(sys_config.update(&tx(500u64.to_be_bytes().to_vec()), false));
+        assert_eq!(sys_config.block_limit, 500);
+    }
+
+    fn scheduled_tx(
+        lock_id: u64,
+        activation_height: u64,
+        output: &[u8],
+        pre_tx_hash: Vec<u8>,
+    ) -> UnverifiedUtxoTransaction {
+        let mut data = activation_height.to_be_bytes().to_vec();
+        data.extend_from_slice(output);
+        UnverifiedUtxoTransaction {
+            transaction: Some(UtxoTransaction {
+                output: data,
+                lock_id: lock_id | LOCK_ID_SCHEDULED_FLAG,
+                pre_tx_hash,
+                ..Default::default()
+            }),
+            transaction_hash: vec![activation_height as u8; 32],
+            witnesses: vec![],
+        }
+    }
+
+    #[test]
+    fn scheduled_change_test() {
+        let mut sys_config = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        let tx = scheduled_tx(
+            LOCK_ID_BLOCK_INTERVAL,
+            10,
+            &6u32.to_be_bytes(),
+            vec![0u8; 33],
+        );
+        assert!(sys_config.update(&tx, false));
+        assert_eq!(sys_config.block_interval, 3);
+        assert_eq!(
+            sys_config.utxo_tx_hashes[&LOCK_ID_BLOCK_INTERVAL],
+            tx.transaction_hash
+        );
+
+        // one pending change for a lock_id
+        let next = scheduled_tx(
+            LOCK_ID_BLOCK_INTERVAL,
+            20,
+            &9u32.to_be_bytes(),
+            vec![10u8; 32],
+        );
+        assert!(!sys_config.update(&next, false));
+
+        // output is checked when scheduled
+        let bad = scheduled_tx(LOCK_ID_BLOCK_LIMIT, 10, &[1; 4], vec![0u8; 33]);
+        assert!(!sys_config.update(&bad, false));
+        assert_eq!(sys_config.pending_changes.len(), 1);
+
+        assert!(sys_config.activate(9).is_empty());
+        assert_eq!(
+            sys_config.activate(10),
+            vec![(LOCK_ID_BLOCK_INTERVAL, tx.transaction_hash.clone(), true)]
+        );
+        assert_eq!(sys_config.block_interval, 6);
+        assert!(sys_config.pending_changes.is_empty());
+        assert!(sys_config.update(&next, false));
+
+        // on restart, an activated scheduled change is applied at once
+        let mut restarted = SystemConfig::new(0, vec![], vec![9; 20], 3, vec![]);
+        assert!(restarted.update(&tx, true));
+        assert_eq!(restarted.block_interval, 6);
+        assert!(restarted.pending_changes.is_empty());
+        assert!(restarted.update(&next, false));
+        assert_eq!(
+            restarted.pending_changes[&LOCK_ID_BLOCK_INTERVAL].activation_height,
+            20
+        );
+    }
+
+    #[test]
+    fn history_test() {
+        let mut history = Vec::new();
+        assert_eq!(history_tx_hash_at(&history, 10), None);
+
+        history.extend(encode_history_record(5, &[5; 32]));
+        history.extend(encode_history_record(8, &[8; 32]));
+        history.extend(encode_history_record(8, &[9; 32]));
+        assert_eq!(history_tx_hash_at(&history, 4), None);
+        assert_eq!(history_tx_hash_at(&history, 5), Some(vec![5; 32]));
+        assert_eq!(history_tx_hash_at(&history, 7), Some(vec![5; 32]));
+        // the later of changes in one block
+        assert_eq!(history_tx_hash_at(&history, 8), Some(vec![9; 32]));
+        assert_eq!(history_tx_hash_at(&history, 100), Some(vec![9; 32]));
+        assert_eq!(
+            history_records(&history),
+            vec![(5, vec![5; 32]), (8, vec![8; 32]), (8, vec![9; 32])]
+        );
+    }
+
+    fn addresses(bytes: &[u8]) -> Vec<u8> {
+        bytes.iter().flat_map(|b| vec![*b; 20]).collect()
+    }
+
+    #[test]
+    fn malformed_output_test() {
+        let committee = |threshold: u32, admins: &[u8]| {
+            let mut data = threshold.to_be_bytes().to_vec();
+            data.extend(addresses(admins));
+            data
+        };
+        for (lock_id, data) in [
+            (LOCK_ID_VERSION, vec![]),
+            (LOCK_ID_VERSION, vec![0, 0, 1]),
+            (LOCK_ID_VERSION, vec![0, 0, 0, 0, 1]),
+            (LOCK_ID_CHAIN_ID, vec![1; 31]),
+            (LOCK_ID_CHAIN_ID, vec![1; 33]),
+            (LOCK_ID_ADMIN, vec![]),
+            (LOCK_ID_ADMIN, vec![1; 19]),
+            (LOCK_ID_ADMIN, vec![1; 21]),
+            (LOCK_ID_BLOCK_INTERVAL, vec![0, 3]),
+            (LOCK_ID_BLOCK_INTERVAL, vec![0; 4]),
+            (LOCK_ID_VALIDATORS, vec![]),
+            (LOCK_ID_VALIDATORS, vec![1; 30]),
+            (LOCK_ID_VALIDATORS, addresses(&[1, 2, 1])),
+            (LOCK_ID_EMERGENCY_BRAKE, vec![0]),
+            (LOCK_ID_EMERGENCY_BRAKE, vec![1, 1]),
+            (LOCK_ID_EMERGENCY_BRAKE, vec![2]),
+            (LOCK_ID_BRAKE_MODE, vec![1, 1]),
+            (LOCK_ID_BRAKE_MODE, vec![4]),
+            (LOCK_ID_BRAKE_MODE, vec![3]),
+            (LOCK_ID_BRAKE_MODE, [vec![3], vec![1; 19]].concat()),
+            (LOCK_ID_BRAKE_MODE, [vec![3], addresses(&[1, 1])].concat()),
+            (LOCK_ID_ADMIN_COMMITTEE, vec![]),
+            (LOCK_ID_ADMIN_COMMITTEE, vec![0, 0, 1]),
+            (LOCK_ID_ADMIN_COMMITTEE, committee(0, &[1])),
+            (LOCK_ID_ADMIN_COMMITTEE, committee(2, &[1])),
+            (LOCK_ID_ADMIN_COMMITTEE, committee(1, &[1, 1])),
+            (LOCK_ID_ADMIN_COMMITTEE, committee(1, &[])),
+            (LOCK_ID_SENDER_ALLOWLIST, vec![1; 21]),
+            (LOCK_ID_SENDER_ALLOWLIST, addresses(&[1, 1])),
+            (LOCK_ID_SENDER_DENYLIST, vec![1; 19]),
+            (LOCK_ID_SENDER_DENYLIST, addresses(&[2, 2])),
+            (LOCK_ID_BLOCK_LIMIT, vec![0; 8]),
+            (LOCK_ID_BLOCK_LIMIT, vec![1; 4]),
+            (LOCK_ID_BLOCK_LIMIT, vec![1; 9]),
+            (LOCK_ID_BUTTON, vec![]),
+        ] {
+            assert!(
+                ConfigChange::decode(lock_id, &data).is_err(),
+                "lock_id {} output {:?}",
+                lock_id,
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_replay_test() {
+        init_test_crypto();
+        let mut sys_config = SystemConfig::new(1, vec![1; 32], vec![1; 20], 3, vec![]);
+        let replay = |sys_config: &mut SystemConfig, lock_id: u64, output: Vec<u8>| {
+            let tx = UnverifiedUtxoTransaction {
+                transaction: Some(UtxoTransaction {
+                    output,
+                    lock_id,
+                    ..Default::default()
+                }),
+                transaction_hash: vec![lock_id as u8; 32],
+                witnesses: vec![],
+            };
+            sys_config.update(&tx, true)
+        };
+
+        // accepted before outputs were checked strictly
+        assert!(replay(
+            &mut sys_config,
+            LOCK_ID_VERSION,
+            vec![0, 0, 0, 2, 9]
+        ));
+        assert_eq!(sys_config.version, 2);
+        assert!(replay(&mut sys_config, LOCK_ID_BLOCK_INTERVAL, vec![0; 4]));
+        assert_eq!(sys_config.block_interval, 0);
+        assert!(replay(
+            &mut sys_config,
+            LOCK_ID_VALIDATORS,
+            addresses(&[1, 2, 1])
+        ));
+        assert_eq!(
+            sys_config.validators,
+            vec![vec![1; 20], vec![2; 20], vec![1; 20]]
+        );
+        for output in [vec![1, 1], vec![4], vec![0], vec![2]] {
+            assert!(replay(&mut sys_config, LOCK_ID_EMERGENCY_BRAKE, output));
+            assert!(sys_config.emergency_brake);
+            assert_eq!(sys_config.brake_mode, BrakeMode::Off);
+        }
+        assert!(replay(&mut sys_config, LOCK_ID_EMERGENCY_BRAKE, vec![]));
+        assert!(!sys_config.emergency_brake);
+        assert!(replay(
+            &mut sys_config,
+            LOCK_ID_SENDER_ALLOWLIST,
+            addresses(&[1, 1])
+        ));
+        assert_eq!(sys_config.sender_allowlist.len(), 1);
+
+        // still rejected
+        assert!(!replay(&mut sys_config, LOCK_ID_VERSION, vec![0, 0, 3]));
+        assert!(!replay(&mut sys_config, LOCK_ID_CHAIN_ID, vec![1; 31]));
+        assert!(!replay(&mut sys_config, LOCK_ID_VALIDATORS, vec![1; 30]));
+        assert_eq!(sys_config.version, 2);
+
+        // new txs are checked strictly
+        assert!(sys_config
+            .check_output(LOCK_ID_VERSION, &[0, 0, 0, 3, 9])
+            .is_err());
+        assert!(sys_config
+            .check_output(LOCK_ID_EMERGENCY_BRAKE, &[1, 1])
+            .is_err());
+    }
+
+    #[test]
+    fn output_encode_test() {
+        for change in [
+            ConfigChange::Version(1),
+            ConfigChange::ChainId(vec![1; 32]),
+            ConfigChange::Admin(vec![1; 20]),
+            ConfigChange::BlockInterval(6),
+            ConfigChange::Validators(vec![vec![1; 20], vec![2; 20]]),
+            ConfigChange::EmergencyBrake(false),
+            ConfigChange::EmergencyBrake(true),
+            ConfigChange::BrakeMode(BrakeMode::Off),
+            ConfigChange::BrakeMode(BrakeMode::All),
+            ConfigChange::BrakeMode(BrakeMode::ContractCreation),
+            ConfigChange::BrakeMode(BrakeMode::Senders(
+                vec![vec![2; 20], vec![1; 20]].into_iter().collect(),
+            )),
+            ConfigChange::AdminCommittee(2, vec![vec![1; 20], vec![2; 20], vec![3; 20]]),
+            ConfigChange::AdminCommittee(0, vec![]),
+            ConfigChange::SenderAllowlist(vec![vec![1; 20]]),
+            ConfigChange::SenderDenylist(vec![]),
+            ConfigChange::BlockLimit(300),
+        ] {
+            assert_eq!(
+                ConfigChange::decode(change.lock_id(), &change.encode()),
+                Ok(change.clone())
+            );
+
+            let (lock_id, data) = change.encode_scheduled(10);
+            assert_eq!(lock_id & !LOCK_ID_SCHEDULED_FLAG, change.lock_id());
+            assert_eq!(decode_scheduled(&data), Some((10, change.encode())));
+        }
+    }
+
+    #[test]
+    fn version_test() {
+        let mut sys_config = SystemConfig::new(1, vec![], vec![9; 20], 3, vec![]);
+        // version only goes up
+        for version in [0, 1] {
+            let data = ConfigChange::Version(version).encode();
+            assert!(sys_config.check_output(LOCK_ID_VERSION, &data).is_err());
+        }
+        let mut tx = sender_list_tx(LOCK_ID_VERSION, &[]);
+        tx.transaction.as_mut().unwrap().output = ConfigChange::Version(2).encode();
+        assert!(sys_config.update(&tx, false));
+        assert_eq!(sys_config.version, 2);
+    }
+
+    #[test]
+    fn init_hash_test() {
+        let mut sys_config = SystemConfig::new(1, vec![1; 4], vec![2; 4], 3, vec![vec![4; 2]]);
+        sys_config.admins = vec![vec![5; 2], vec![6; 2]];
+        sys_config.admin_threshold = 2;
+        let data = sys_config.init_encode();
+        assert_eq!(
+            hex::encode(&data),
+            "00000001000000010000000000000004010101010000000000000004020202020000000300000000000000010000000000000002040400000002000000000000000200000000000000020505000000000000000206060000000000000064"
+        );
+        assert_eq!(
+            hex::encode(Eth.hash(&data)),
+            "af059902142cc2a1f204d179d162d930ef1847237d9dcb8a02ea36a18ff48b4b"
+        );
+
+        // items init_sys_config.toml can't set aren't covered
+        let mut other = sys_config.clone();
+        other.emergency_brake = true;
+        other.brake_mode = BrakeMode::All;
+        other.sender_allowlist.insert(vec![7; 20]);
+        other.sender_denylist.insert(vec![8; 20]);
+        other.utxo_tx_hashes.insert(LOCK_ID_VERSION, vec![1; 32]);
+        assert_eq!(other.init_encode(), data);
+
+        // items are framed, moving an address across items changes the encoding
+        let mut moved = sys_config;
+        let admin = moved.admins.remove(0);
+        moved.validators.push(admin);
+        assert_ne!(moved.init_encode(), data);
     }
 }